@@ -7,6 +7,9 @@ fn main() -> Result<(), String> {
 	if target_arch == "x86_64" && target_os == "none" {
 		let mut nasm = nasm_rs::Build::new();
 		nasm.file("src/arch/x86_64/entry.asm");
+		if env::var_os("CARGO_FEATURE_LA57").is_some() {
+			nasm.define("LA57", None);
+		}
 		let objects = nasm.compile_objects()?;
 
 		let mut cc = cc::Build::new();
@@ -31,6 +31,8 @@ pub struct BootInfo {
 	pub hcip: [u8; 4],
 	pub hcgateway: [u8; 4],
 	pub hcmask: [u8; 4],
+	pub ramdisk_image: u64,
+	pub ramdisk_len: u64,
 }
 
 impl BootInfo {
@@ -63,6 +65,8 @@ impl BootInfo {
 			hcip: [255, 255, 255, 255],
 			hcgateway: [255, 255, 255, 255],
 			hcmask: [255, 255, 255, 0],
+			ramdisk_image: 0,
+			ramdisk_len: 0,
 		}
 	}
 }
@@ -96,6 +100,8 @@ impl fmt::Debug for BootInfo {
 		writeln!(f, "current_boot_id {}", self.current_boot_id)?;
 		writeln!(f, "uartport {:#x}", self.uartport)?;
 		writeln!(f, "single_kernel {}", self.single_kernel)?;
-		writeln!(f, "uhyve {}", self.uhyve)
+		writeln!(f, "uhyve {}", self.uhyve)?;
+		writeln!(f, "ramdisk_image {:#x}", self.ramdisk_image)?;
+		writeln!(f, "ramdisk_len {}", self.ramdisk_len)
 	}
 }
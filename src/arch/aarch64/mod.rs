@@ -7,6 +7,12 @@ pub use crate::arch::bootinfo::*;
 use crate::arch::paging::*;
 use crate::arch::serial::SerialPort;
 use core::arch::asm;
+use core::cmp;
+use core::mem::MaybeUninit;
+use core::ops::Range;
+use core::ptr;
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 extern "C" {
 	static kernel_end: u8;
@@ -20,6 +26,12 @@ extern "C" {
 
 pub const ELF_ARCH: u16 = goblin::elf::header::EM_AARCH64;
 pub const R_RELATIVE: u32 = goblin::elf::reloc::R_AARCH64_RELATIVE;
+pub const R_ABS64: u32 = goblin::elf::reloc::R_AARCH64_ABS64;
+pub const R_GLOB_DAT: u32 = goblin::elf::reloc::R_AARCH64_GLOB_DAT;
+pub const R_JUMP_SLOT: u32 = goblin::elf::reloc::R_AARCH64_JUMP_SLOT;
+pub const R_IRELATIVE: u32 = goblin::elf::reloc::R_AARCH64_IRELATIVE;
+/// Required alignment of the kernel entry point (A64 instructions are 4 bytes each)
+pub const ENTRY_ALIGN: u64 = 4;
 
 /// start address of the RAM at Qemu's virt emulation
 const RAM_START: u64 = 0x40000000;
@@ -36,6 +48,7 @@ const PT_SELF: u64 = 1 << 55;
 
 // VARIABLES
 pub static mut BOOT_INFO: BootInfo = BootInfo::new();
+static KERNEL_MEMORY_ALLOCATED: AtomicBool = AtomicBool::new(false);
 static mut COM1: SerialPort = SerialPort::new(SERIAL_PORT_ADDRESS);
 
 pub fn message_output_init() {
@@ -48,10 +61,88 @@ pub fn output_message_byte(byte: u8) {
 	}
 }
 
-pub unsafe fn get_memory(_memory_size: u64) -> u64 {
-	align_up!(&kernel_end as *const u8 as u64, LargePageSize::SIZE as u64)
+/// Copies `src` into `dst`, which must be of the same length.
+pub fn copy_memory(dst: &mut [MaybeUninit<u8>], src: &[u8]) {
+	assert_eq!(dst.len(), src.len());
+
+	// `dst` is borrowed mutably, so it cannot overlap with `src`.
+	unsafe {
+		ptr::copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr().cast::<u8>(), src.len());
+	}
+}
+
+/// Maps the frames of `src` at `dst` instead of copying them, such as for read-only kernel segments.
+///
+/// The kernel is loaded with the MMU disabled on AArch64, so nothing is shared and the caller copies all of `src`.
+pub fn share_memory(dst: &mut [MaybeUninit<u8>], src: &[u8], _p_flags: u32) -> usize {
+	assert_eq!(dst.len(), src.len());
+	0
+}
+
+/// Zeroes `dst`.
+pub fn zero_memory(dst: &mut [MaybeUninit<u8>]) {
+	unsafe {
+		ptr::write_bytes(dst.as_mut_ptr(), 0, dst.len());
+	}
+}
+
+/// Returns the initial ramdisk, if any.
+///
+/// Initial ramdisks are not supported on AArch64 yet, since there is no way of passing them to the loader.
+pub unsafe fn find_initrd() -> Option<&'static [u8]> {
+	None
+}
+
+pub unsafe fn get_memory(_memory_size: u64, align: u64) -> u64 {
+	let align = cmp::max(align, LargePageSize::SIZE as u64);
+	align_up!(&kernel_end as *const u8 as u64, align)
+}
+
+/// Allocates memory for loading the kernel.
+///
+/// The kernel is always placed right after the loader, so this may only be called once,
+/// unless the memory is freed with [`release_kernel_memory`].
+pub fn allocate_kernel_memory(size: usize, align: usize) -> &'static mut [MaybeUninit<u8>] {
+	assert!(
+		!KERNEL_MEMORY_ALLOCATED.swap(true, Ordering::Relaxed),
+		"Memory for the kernel has already been allocated"
+	);
+
+	unsafe {
+		let address = get_memory(size as u64, align as u64);
+		slice::from_raw_parts_mut(address as *mut MaybeUninit<u8>, size)
+	}
+}
+
+/// A saved state of the memory allocations, see [`release_kernel_memory`].
+pub struct KernelMemoryMark(());
+
+/// Saves the state of the memory allocations before allocating memory for a kernel.
+pub fn mark_kernel_memory() -> KernelMemoryMark {
+	KernelMemoryMark(())
+}
+
+/// Frees `memory` from [`allocate_kernel_memory`], e.g., after loading a kernel into it failed.
+///
+/// The kernel is always placed right after the loader, so this only allows allocating its memory again.
+pub fn release_kernel_memory(_mark: KernelMemoryMark, _memory: &'static mut [MaybeUninit<u8>]) {
+	KERNEL_MEMORY_ALLOCATED.store(false, Ordering::Relaxed);
 }
 
+/// Allocates memory for temporary data, such as a decompressed kernel image.
+///
+/// The memory after the loader is reserved for the kernel, so this is not supported on AArch64 yet
+/// and always returns None.
+pub fn allocate_scratch_memory(_size: usize) -> Option<&'static mut [MaybeUninit<u8>]> {
+	None
+}
+
+/// Makes the given page-aligned range of the loaded kernel read-only, such as its RELRO region after relocating it.
+///
+/// The translation tables are only built when booting the kernel and map all of its memory with the same
+/// attributes, so this is not supported on AArch64 yet and does nothing.
+pub fn write_protect(_range: Range<u64>) {}
+
 pub fn find_kernel() -> &'static [u8] {
 	align_data::include_aligned!(goblin::elf64::header::Header, env!("HERMIT_APP"))
 }
@@ -61,6 +152,7 @@ pub unsafe fn boot_kernel(
 	virtual_address: u64,
 	mem_size: u64,
 	entry_point: u64,
+	_stack_executable: bool,
 ) -> ! {
 	// Jump to the kernel entry point and provide the Multiboot information to it.
 	loaderlog!(
@@ -119,8 +211,6 @@ pub unsafe fn boot_kernel(
 		*entry = RAM_START + (i * BasePageSize::SIZE) as u64 + PT_MEM;
 	}
 
-	let func: extern "C" fn(boot_info: &'static mut BootInfo) -> ! =
-		core::mem::transmute(entry_point);
 	COM1.set_port(0x1000);
 
 	// Load TTBRx
@@ -147,5 +237,13 @@ pub unsafe fn boot_kernel(
 	/* Memory barrier */
 	asm!("dsb sy", options(nostack));
 
-	func(&mut BOOT_INFO)
+	// Enter the kernel according to the AArch64 Procedure Call Standard (AAPCS64),
+	// which passes the pointer to the boot information as the first argument in x0.
+	asm!(
+		"blr {entry_point}",
+		"udf #0",
+		entry_point = in(reg) entry_point,
+		in("x0") &BOOT_INFO as *const BootInfo,
+		options(noreturn),
+	)
 }
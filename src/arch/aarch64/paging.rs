@@ -1,4 +1,13 @@
+use core::arch::asm;
 use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use goblin::elf64::program_header::{PF_W, PF_X};
+
+extern "C" {
+	static mut l0_pgtable: PageTable;
+}
 
 /// Number of Offset bits of a virtual address for a 4 KiB page, which are shifted away to get its Page Frame Number (PFN).
 pub const PAGE_BITS: usize = 12;
@@ -9,6 +18,158 @@ pub const PAGE_MAP_BITS: usize = 9;
 /// A mask where PAGE_MAP_BITS are set to calculate a table index.
 pub const PAGE_MAP_MASK: usize = 0x1FF;
 
+/// Number of translation table levels in the hierarchy (L0Table, L1Table, L2Table, L3Table).
+const PAGE_LEVELS: usize = 4;
+
+/// Index of the entry in the root table that refers to the root table itself.
+const RECURSIVE_INDEX: usize = 511;
+
+/// Mask of the output address of a descriptor (bits 47 through 12).
+const PHYSICAL_ADDRESS_MASK: usize = 0x0000_FFFF_FFFF_F000;
+
+/// Maximum number of translation tables that can be allocated for mappings besides the root table.
+const MAX_TABLES: usize = 16;
+
+bitflags::bitflags! {
+	/// Possible flags for a descriptor in either table (L0Table, L1Table, L2Table, L3Table)
+	///
+	/// See Arm ARM, D5.3 "VMSAv8-64 translation table format descriptors"
+	pub struct PageTableEntryFlags: usize {
+		/// Set if this descriptor is valid and points to a page, block, or table.
+		const VALID = 1 << 0;
+
+		/// Set if this descriptor references a table (L0Table, L1Table, L2Table) or a page (L3Table).
+		/// Otherwise, it references a 1 GiB (L1Table) or 2 MiB (L2Table) block.
+		const TABLE_OR_PAGE = 1 << 1;
+
+		/// AttrIndx selecting Device-nGnRE memory from MAIR_EL1 (MT_DEVICE_nGnRE).
+		const DEVICE_NGNRE = 0b001 << 2;
+
+		/// AttrIndx selecting Normal Non-cacheable memory from MAIR_EL1 (MT_NORMAL_NC).
+		const NORMAL_NC = 0b011 << 2;
+
+		/// AttrIndx selecting Normal Write-Back memory from MAIR_EL1 (MT_NORMAL).
+		const NORMAL = 0b100 << 2;
+
+		/// AP[1]: Set if memory referenced by this descriptor shall be accessible from EL0.
+		const USER_ACCESSIBLE = 1 << 6;
+
+		/// AP[2]: Set if memory referenced by this descriptor shall be read-only.
+		const READ_ONLY = 1 << 7;
+
+		/// Set if memory referenced by this descriptor is Inner Shareable.
+		const INNER_SHAREABLE = 0b11 << 8;
+
+		/// Set if software has accessed this descriptor. Accessing memory with this flag cleared faults.
+		const ACCESSED = 1 << 10;
+
+		/// Set if this address translation is specific to the current ASID.
+		const NOT_GLOBAL = 1 << 11;
+
+		/// Set if code execution at EL1 shall be disabled for memory referenced by this descriptor.
+		const PRIVILEGED_EXECUTE_NEVER = 1 << 53;
+
+		/// Set if code execution at EL0 shall be disabled for memory referenced by this descriptor.
+		const USER_EXECUTE_NEVER = 1 << 54;
+
+		/// Ignored by the CPU: Marks the entry of the root table that refers to the root table itself.
+		const SELF = 1 << 55;
+	}
+}
+
+/// An error that occurred while mapping pages.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MapError {
+	/// There are no translation tables left for the mapping.
+	OutOfMemory,
+}
+
+impl PageTableEntryFlags {
+	/// An empty set of flags for unused/zeroed table entries.
+	/// Needed as long as empty() is no const function.
+	const BLANK: PageTableEntryFlags = PageTableEntryFlags { bits: 0 };
+
+	/// Returns the flags for mapping memory-mapped I/O of a device.
+	#[allow(dead_code)]
+	pub fn device() -> Self {
+		PageTableEntryFlags::DEVICE_NGNRE
+			| PageTableEntryFlags::PRIVILEGED_EXECUTE_NEVER
+			| PageTableEntryFlags::USER_EXECUTE_NEVER
+	}
+
+	/// Returns the flags for mapping an ELF segment with the given permissions (`p_flags`).
+	///
+	/// Only segments with `PF_W` are writable and only segments with `PF_X` are executable.
+	#[allow(dead_code)]
+	pub fn from_segment_flags(p_flags: u32) -> Self {
+		let mut flags = PageTableEntryFlags::NORMAL;
+		if p_flags & PF_W == 0 {
+			flags.insert(PageTableEntryFlags::READ_ONLY);
+		}
+		if p_flags & PF_X == 0 {
+			flags.insert(
+				PageTableEntryFlags::PRIVILEGED_EXECUTE_NEVER
+					| PageTableEntryFlags::USER_EXECUTE_NEVER,
+			);
+		}
+		flags
+	}
+}
+
+/// A descriptor in either table (L0Table, L1Table, L2Table, L3Table)
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct PageTableEntry {
+	/// Physical memory address this entry refers, combined with flags from PageTableEntryFlags.
+	physical_address_and_flags: usize,
+}
+
+impl PageTableEntry {
+	/// Returns whether this entry is valid.
+	fn is_present(&self) -> bool {
+		(self.physical_address_and_flags & PageTableEntryFlags::VALID.bits()) != 0
+	}
+
+	/// Returns whether this entry references a table (L0Table, L1Table, L2Table) or a page (L3Table)
+	/// instead of a block.
+	fn is_table_or_page(&self) -> bool {
+		(self.physical_address_and_flags & PageTableEntryFlags::TABLE_OR_PAGE.bits()) != 0
+	}
+
+	/// Returns the physical address this entry refers to.
+	fn address(&self) -> usize {
+		self.physical_address_and_flags & PHYSICAL_ADDRESS_MASK
+	}
+
+	/// Marks this entry as invalid, clearing its address and all flags.
+	fn clear(&mut self) {
+		self.physical_address_and_flags = 0;
+	}
+
+	/// Mark this as a valid entry and set address translation and flags.
+	///
+	/// # Arguments
+	///
+	/// * `physical_address` - The physical memory address this entry shall translate to
+	/// * `flags` - Flags from PageTableEntryFlags (note that the VALID, ACCESSED, and INNER_SHAREABLE flags are set automatically)
+	fn set(&mut self, physical_address: usize, flags: PageTableEntryFlags) {
+		// Verify that the offset bits for a 4 KiB page are zero.
+		assert_eq!(
+			physical_address % BasePageSize::SIZE,
+			0,
+			"Physical address is not on a 4 KiB page boundary (physical_address = {:#x})",
+			physical_address
+		);
+
+		self.physical_address_and_flags = physical_address
+			| (PageTableEntryFlags::VALID
+				| PageTableEntryFlags::ACCESSED
+				| PageTableEntryFlags::INNER_SHAREABLE
+				| flags)
+				.bits();
+	}
+}
+
 /// A generic interface to support all possible page sizes.
 ///
 /// This is defined as a subtrait of Copy to enable #[derive(Clone, Copy)] for Page.
@@ -16,20 +177,41 @@ pub const PAGE_MAP_MASK: usize = 0x1FF;
 pub trait PageSize: Copy {
 	/// The page size in bytes.
 	const SIZE: usize;
+
+	/// The table level at which a page of this size is mapped (from 0 for L3Table through 2 for L1Table).
+	/// Implemented as a numeric value to enable numeric comparisons.
+	const MAP_LEVEL: usize;
+
+	/// Any extra flag that needs to be set to map a page of this size.
+	/// For example: PageTableEntryFlags::TABLE_OR_PAGE
+	const MAP_EXTRA_FLAG: PageTableEntryFlags;
 }
 
-/// A 4 KiB page mapped in the PGT.
+/// A 4 KiB page mapped in the L3Table.
 #[derive(Clone, Copy)]
 pub enum BasePageSize {}
 impl PageSize for BasePageSize {
 	const SIZE: usize = 4096;
+	const MAP_LEVEL: usize = 0;
+	const MAP_EXTRA_FLAG: PageTableEntryFlags = PageTableEntryFlags::TABLE_OR_PAGE;
 }
 
-/// A 2 MiB page mapped in the PDT.
+/// A 2 MiB block mapped in the L2Table.
 #[derive(Clone, Copy)]
 pub enum LargePageSize {}
 impl PageSize for LargePageSize {
 	const SIZE: usize = 2 * 1024 * 1024;
+	const MAP_LEVEL: usize = 1;
+	const MAP_EXTRA_FLAG: PageTableEntryFlags = PageTableEntryFlags::BLANK;
+}
+
+/// A 1 GiB block mapped in the L1Table.
+#[derive(Clone, Copy)]
+pub enum HugePageSize {}
+impl PageSize for HugePageSize {
+	const SIZE: usize = 1024 * 1024 * 1024;
+	const MAP_LEVEL: usize = 2;
+	const MAP_EXTRA_FLAG: PageTableEntryFlags = PageTableEntryFlags::BLANK;
 }
 
 /// A memory page of the size given by S.
@@ -42,3 +224,285 @@ struct Page<S: PageSize> {
 	/// Required by Rust to support the S parameter.
 	size: PhantomData<S>,
 }
+
+impl<S: PageSize> Page<S> {
+	/// Flushes this page from the TLBs of all CPUs in the Inner Shareable domain.
+	fn flush_from_tlb(&self) {
+		unsafe {
+			asm!(
+				"dsb ishst",
+				"tlbi vaae1is, {}",
+				"dsb ish",
+				"isb",
+				in(reg) self.virtual_address >> PAGE_BITS,
+				options(nostack, preserves_flags),
+			);
+		}
+	}
+
+	/// Returns whether the given virtual address is a valid one in the lower VA range (TTBR0_EL1).
+	///
+	/// With 4-level translation tables, 48 bits are used for virtual memory addresses.
+	fn is_valid_address(virtual_address: usize) -> bool {
+		virtual_address < 0x1_0000_0000_0000
+	}
+
+	/// Returns a Page including the given virtual address.
+	/// That means, the address is rounded down to a page size boundary.
+	fn including_address(virtual_address: usize) -> Self {
+		assert!(Self::is_valid_address(virtual_address));
+
+		Self {
+			virtual_address: align_down!(virtual_address, S::SIZE),
+			size: PhantomData,
+		}
+	}
+
+	/// Returns a PageIter to iterate from the given first Page to the given last Page (inclusive).
+	fn range(first: Self, last: Self) -> PageIter<S> {
+		assert!(first.virtual_address <= last.virtual_address);
+		PageIter {
+			current: first,
+			last,
+		}
+	}
+
+	/// Returns the index of this page in the table of the given level.
+	fn table_index(&self, level: usize) -> usize {
+		assert!(level >= S::MAP_LEVEL);
+		debug_assert!(level < PAGE_LEVELS);
+		self.virtual_address >> PAGE_BITS >> (level * PAGE_MAP_BITS) & PAGE_MAP_MASK
+	}
+}
+
+/// An iterator to walk through a range of pages of size S.
+struct PageIter<S: PageSize> {
+	current: Page<S>,
+	last: Page<S>,
+}
+
+impl<S: PageSize> Iterator for PageIter<S> {
+	type Item = Page<S>;
+
+	fn next(&mut self) -> Option<Page<S>> {
+		if self.current.virtual_address <= self.last.virtual_address {
+			let p = self.current;
+			self.current.virtual_address += S::SIZE;
+			Some(p)
+		} else {
+			None
+		}
+	}
+}
+
+/// A translation table (L0Table, L1Table, L2Table, L3Table)
+///
+/// The loader runs with the MMU disabled, so tables are accessed through their physical addresses.
+#[repr(C, align(4096))]
+struct PageTable {
+	entries: [PageTableEntry; 1 << PAGE_MAP_BITS],
+}
+
+/// Translation tables for mappings besides the root table, which are handed out by [`allocate_table`].
+static mut TABLES: [PageTable; MAX_TABLES] = [PageTable::EMPTY; MAX_TABLES];
+
+/// Number of tables of [`TABLES`] that have been allocated since the last [`init`].
+static ALLOCATED_TABLES: AtomicUsize = AtomicUsize::new(0);
+
+impl PageTable {
+	const EMPTY: PageTable = PageTable {
+		entries: [PageTableEntry {
+			physical_address_and_flags: 0,
+		}; 1 << PAGE_MAP_BITS],
+	};
+
+	/// Marks all entries of this table as invalid.
+	fn zero(&mut self) {
+		for entry in self.entries.iter_mut() {
+			entry.clear();
+		}
+	}
+
+	/// Returns the table referenced by the given entry of this table at the given level, if any.
+	///
+	/// Panics if the entry references a block instead of a table.
+	fn subtable(&mut self, index: usize, level: usize) -> Option<&mut PageTable> {
+		let entry = &self.entries[index];
+		if !entry.is_present() {
+			return None;
+		}
+
+		assert!(
+			entry.is_table_or_page(),
+			"Level {level} entry {index} maps a block instead of a table"
+		);
+		Some(unsafe { &mut *(entry.address() as *mut PageTable) })
+	}
+
+	/// Returns the table referenced by the given entry of this table at the given level,
+	/// allocating an empty one if there is none.
+	fn subtable_or_allocate(
+		&mut self,
+		index: usize,
+		level: usize,
+	) -> Result<&mut PageTable, MapError> {
+		if !self.entries[index].is_present() {
+			let subtable = allocate_table()?;
+			self.entries[index].set(
+				subtable as *mut PageTable as usize,
+				PageTableEntryFlags::TABLE_OR_PAGE,
+			);
+		}
+
+		Ok(self.subtable(index, level).unwrap())
+	}
+
+	/// Returns the entry mapping the given page, walking down from this root table, if its tables exist.
+	fn get_page_table_entry_mut<S: PageSize>(
+		&mut self,
+		page: Page<S>,
+	) -> Option<&mut PageTableEntry> {
+		let mut table = self;
+		for level in (S::MAP_LEVEL + 1..PAGE_LEVELS).rev() {
+			table = table.subtable(page.table_index(level), level)?;
+		}
+
+		Some(&mut table.entries[page.table_index(S::MAP_LEVEL)])
+	}
+
+	/// Maps a single page, walking down from this root table and allocating missing tables.
+	fn map_page<S: PageSize>(
+		&mut self,
+		page: Page<S>,
+		physical_address: usize,
+		flags: PageTableEntryFlags,
+	) -> Result<(), MapError> {
+		let mut table = self;
+		for level in (S::MAP_LEVEL + 1..PAGE_LEVELS).rev() {
+			table = table.subtable_or_allocate(page.table_index(level), level)?;
+		}
+
+		let entry = &mut table.entries[page.table_index(S::MAP_LEVEL)];
+		let flush = entry.is_present();
+		entry.set(physical_address, flags | S::MAP_EXTRA_FLAG);
+		if flush {
+			page.flush_from_tlb();
+		}
+
+		Ok(())
+	}
+}
+
+/// Returns a zeroed translation table from [`TABLES`].
+fn allocate_table() -> Result<&'static mut PageTable, MapError> {
+	let index = ALLOCATED_TABLES.fetch_add(1, Ordering::Relaxed);
+	if index >= MAX_TABLES {
+		return Err(MapError::OutOfMemory);
+	}
+
+	let table = unsafe { &mut *ptr::addr_of_mut!(TABLES[index]) };
+	table.zero();
+	Ok(table)
+}
+
+/// Returns the root translation table (L0Table).
+fn root_table() -> &'static mut PageTable {
+	unsafe { &mut *ptr::addr_of_mut!(l0_pgtable) }
+}
+
+#[inline]
+fn get_page_range<S: PageSize>(virtual_address: usize, count: usize) -> PageIter<S> {
+	let first_page = Page::<S>::including_address(virtual_address);
+	let last_page = Page::<S>::including_address(virtual_address + (count - 1) * S::SIZE);
+	Page::range(first_page, last_page)
+}
+
+/// Clears the root table and releases all other tables, removing all mappings.
+///
+/// The last entry of the root table is set up to refer to the root table itself, which the kernel relies on.
+#[allow(dead_code)]
+pub fn init() {
+	ALLOCATED_TABLES.store(0, Ordering::Relaxed);
+
+	let root_table = root_table();
+	root_table.zero();
+	let root_address = root_table as *mut PageTable as usize;
+	root_table.entries[RECURSIVE_INDEX].set(
+		root_address,
+		PageTableEntryFlags::TABLE_OR_PAGE | PageTableEntryFlags::SELF,
+	);
+}
+
+/// Returns the physical address of the root translation table, which is to be loaded into TTBR0_EL1.
+#[allow(dead_code)]
+pub fn root_table_address() -> usize {
+	root_table() as *mut PageTable as usize
+}
+
+#[allow(dead_code)]
+pub fn map<S: PageSize>(
+	virtual_address: usize,
+	physical_address: usize,
+	count: usize,
+	flags: PageTableEntryFlags,
+) -> Result<(), MapError> {
+	let range = get_page_range::<S>(virtual_address, count);
+	let root_table = root_table();
+	let mut current_physical_address = physical_address;
+	for page in range {
+		root_table.map_page(page, current_physical_address, flags)?;
+		current_physical_address += S::SIZE;
+	}
+
+	Ok(())
+}
+
+/// Unmaps a continuous range of pages, which all have to be mapped.
+///
+/// Returns the number of descriptors that have been cleared.
+#[allow(dead_code)]
+pub fn unmap<S: PageSize>(virtual_address: usize, count: usize) -> usize {
+	let range = get_page_range::<S>(virtual_address, count);
+	let root_table = root_table();
+	let mut cleared = 0;
+	for page in range {
+		let entry = root_table
+			.get_page_table_entry_mut(page)
+			.filter(|entry| entry.is_present())
+			.unwrap_or_else(|| {
+				panic!(
+					"Trying to unmap page {:#x}, which is not mapped",
+					page.virtual_address
+				)
+			});
+
+		entry.clear();
+		page.flush_from_tlb();
+		cleared += 1;
+	}
+
+	cleared
+}
+
+/// Changes the flags of a continuous range of mapped pages without changing their physical addresses.
+///
+/// The VALID, ACCESSED, and INNER_SHAREABLE flags of each page are preserved, while all other flags are replaced by `flags`.
+#[allow(dead_code)]
+pub fn protect<S: PageSize>(virtual_address: usize, count: usize, flags: PageTableEntryFlags) {
+	let range = get_page_range::<S>(virtual_address, count);
+	let root_table = root_table();
+	for page in range {
+		let entry = root_table
+			.get_page_table_entry_mut(page)
+			.filter(|entry| entry.is_present())
+			.unwrap_or_else(|| {
+				panic!(
+					"Trying to protect page {:#x}, which is not mapped",
+					page.virtual_address
+				)
+			});
+
+		entry.set(entry.address(), flags | S::MAP_EXTRA_FLAG);
+		page.flush_from_tlb();
+	}
+}
@@ -32,6 +32,10 @@ pub struct BootInfo {
 	pub hcgateway: [u8; 4],
 	pub hcmask: [u8; 4],
 	pub tls_align: u64,
+	pub memory_map: u64,
+	pub memory_map_len: u64,
+	pub ramdisk_image: u64,
+	pub ramdisk_len: u64,
 }
 
 impl BootInfo {
@@ -64,10 +68,89 @@ impl BootInfo {
 			hcgateway: [255, 255, 255, 255],
 			hcmask: [255, 255, 255, 0],
 			tls_align: 0,
+			memory_map: 0,
+			memory_map_len: 0,
+			ramdisk_image: 0,
+			ramdisk_len: 0,
 		}
 	}
 }
 
+/// Collects the fields of a [`BootInfo`] for handing it to the kernel.
+///
+/// [`Self::finish`] checks that all fields required by the kernel have been set.
+pub struct BootInfoBuilder {
+	boot_info: BootInfo,
+	has_image: bool,
+	has_stack: bool,
+	has_memory_map: bool,
+}
+
+impl BootInfoBuilder {
+	/// Starts with the given boot information, e.g., with fields that have been set by [`TlsInfo::insert_into`].
+	///
+	/// [`TlsInfo::insert_into`]: crate::kernel::TlsInfo::insert_into
+	pub fn new(boot_info: BootInfo) -> Self {
+		Self {
+			boot_info,
+			has_image: false,
+			has_stack: false,
+			has_memory_map: false,
+		}
+	}
+
+	/// Returns the boot information collected so far.
+	pub fn boot_info(&self) -> &BootInfo {
+		&self.boot_info
+	}
+
+	/// Sets the address and the size of the loaded kernel.
+	pub fn image(&mut self, base: u64, size: u64) -> &mut Self {
+		self.boot_info.base = base;
+		self.boot_info.image_size = size;
+		self.has_image = true;
+		self
+	}
+
+	/// Sets the address of the Multiboot information.
+	pub fn multiboot(&mut self, mb_info: u64) -> &mut Self {
+		self.boot_info.mb_info = mb_info;
+		self
+	}
+
+	/// Sets the address and the length of the kernel command line.
+	pub fn cmdline(&mut self, address: u64, size: u64) -> &mut Self {
+		self.boot_info.cmdline = address;
+		self.boot_info.cmdsize = size;
+		self
+	}
+
+	/// Sets the start address of the kernel stack.
+	pub fn stack(&mut self, address: u64) -> &mut Self {
+		self.boot_info.current_stack_address = address;
+		self.has_stack = true;
+		self
+	}
+
+	/// Sets the address and the number of entries of the memory map.
+	pub fn memory_map(&mut self, address: u64, len: u64) -> &mut Self {
+		self.boot_info.memory_map = address;
+		self.boot_info.memory_map_len = len;
+		self.has_memory_map = true;
+		self
+	}
+
+	/// Returns the finished boot information.
+	///
+	/// Panics if the kernel image, the stack, or the memory map have not been set.
+	pub fn finish(self) -> BootInfo {
+		assert!(self.has_image, "BootInfo is missing the kernel image");
+		assert!(self.has_stack, "BootInfo is missing the kernel stack");
+		assert!(self.has_memory_map, "BootInfo is missing the memory map");
+		self.boot_info
+	}
+}
+
 impl fmt::Debug for BootInfo {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		writeln!(f, "magic_number {:#x}", self.magic_number)?;
@@ -97,6 +180,10 @@ impl fmt::Debug for BootInfo {
 		writeln!(f, "current_boot_id {}", self.current_boot_id)?;
 		writeln!(f, "uartport {:#x}", self.uartport)?;
 		writeln!(f, "single_kernel {}", self.single_kernel)?;
-		writeln!(f, "uhyve {}", self.uhyve)
+		writeln!(f, "uhyve {}", self.uhyve)?;
+		writeln!(f, "memory_map {:#x}", self.memory_map)?;
+		writeln!(f, "memory_map_len {}", self.memory_map_len)?;
+		writeln!(f, "ramdisk_image {:#x}", self.ramdisk_image)?;
+		writeln!(f, "ramdisk_len {}", self.ramdisk_len)
 	}
 }
@@ -129,10 +129,28 @@ L1:
     test edx, 1 << 29 ; Test if the LM-bit, which is bit 29, is set in the D-register.
     jz Linvalid ; They aren't, there is no long mode.
 
+%ifdef LA57
+    ; do we support 5-level paging?
+    mov eax, 0x7
+    xor ecx, ecx
+    cpuid
+    test ecx, 1 << 16 ; Test if the LA57-bit, which is bit 16, is set in the C-register.
+    jz Linvalid ; It isn't, there is no 5-level paging.
+
+    ; Set CR3
+    mov eax, boot_pml5
+    mov cr3, eax
+
+    ; we need to enable 5-level paging before paging itself is enabled
+    mov eax, cr4
+    or eax, 1 << 12
+    mov cr4, eax
+%else
     ; Set CR3
     mov eax, boot_pml4
     ;or eax, (1 << 0)        ; set present bit
     mov cr3, eax
+%endif
 
     ; we need to enable PAE modus
     mov eax, cr4
@@ -203,10 +221,21 @@ boot_stack:
 
 ; Bootstrap page tables are used during the initialization.
 ALIGN 4096
+%ifdef LA57
+; With 5-level paging, the recursive mapping lives in the PML5.
+boot_pml5:
+    DQ boot_pml4 + 0x3  ; PG_PRESENT | PG_RW
+    times 510 DQ 0      ; PAGE_MAP_ENTRIES - 2
+    DQ boot_pml5 + 0x3  ; PG_PRESENT | PG_RW
+boot_pml4:
+    DQ boot_pdpt + 0x3  ; PG_PRESENT | PG_RW
+    times 511 DQ 0      ; PAGE_MAP_ENTRIES - 1
+%else
 boot_pml4:
     DQ boot_pdpt + 0x3  ; PG_PRESENT | PG_RW
     times 510 DQ 0      ; PAGE_MAP_ENTRIES - 2
     DQ boot_pml4 + 0x3  ; PG_PRESENT | PG_RW
+%endif
 boot_pdpt:
     DQ boot_pgd + 0x3   ; PG_PRESENT | PG_RW
     times 511 DQ 0      ; PAGE_MAP_ENTRIES - 1
@@ -0,0 +1,123 @@
+use core::cmp;
+
+/// Maximum number of entries in a [`MemoryMap`].
+const MEMORY_MAP_CAPACITY: usize = 64;
+
+/// The kind of a physical memory region.
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MemoryKind {
+	/// Free memory the kernel may use.
+	Usable = 1,
+
+	/// Memory reserved by the firmware or otherwise unusable.
+	Reserved = 2,
+
+	/// Memory used by the loader, including memory that was handed out by the physical memory manager.
+	Loader = 3,
+
+	/// Memory holding the loaded kernel image.
+	Kernel = 4,
+
+	/// Memory holding the boot information and data referenced by it.
+	BootInfo = 5,
+
+	/// Memory holding the initial ramdisk.
+	Ramdisk = 6,
+}
+
+/// An entry of the memory map passed to the kernel.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryMapEntry {
+	/// Physical start address of this region.
+	pub base: u64,
+
+	/// Length of this region in bytes.
+	pub len: u64,
+
+	/// Kind of this region.
+	pub kind: MemoryKind,
+}
+
+/// A physical memory map with a fixed capacity.
+///
+/// Entries are sorted by address, do not overlap, and adjacent entries of the same kind are merged.
+/// Once the map is full, further regions are merged into the last entry, which is then reserved.
+pub struct MemoryMap {
+	entries: [MemoryMapEntry; MEMORY_MAP_CAPACITY],
+	len: usize,
+	truncated: bool,
+}
+
+impl MemoryMap {
+	pub const fn new() -> Self {
+		Self {
+			entries: [MemoryMapEntry {
+				base: 0,
+				len: 0,
+				kind: MemoryKind::Reserved,
+			}; MEMORY_MAP_CAPACITY],
+			len: 0,
+			truncated: false,
+		}
+	}
+
+	pub fn entries(&self) -> &[MemoryMapEntry] {
+		&self.entries[..self.len]
+	}
+
+	/// Returns whether regions have been merged into a reserved entry, since the map was full.
+	pub fn is_truncated(&self) -> bool {
+		self.truncated
+	}
+
+	/// Marks the region `[base, base + len)` as `kind`, overriding the kinds of earlier inserted regions.
+	pub fn insert(&mut self, base: u64, len: u64, kind: MemoryKind) {
+		if len == 0 {
+			return;
+		}
+		let end = base + len;
+
+		let old_entries = self.entries;
+		let old_entries = &old_entries[..self.len];
+		self.len = 0;
+
+		for entry in old_entries.iter().filter(|entry| entry.base < base) {
+			let entry_end = cmp::min(entry.base + entry.len, base);
+			self.push(entry.base, entry_end - entry.base, entry.kind);
+		}
+
+		self.push(base, len, kind);
+
+		for entry in old_entries
+			.iter()
+			.filter(|entry| entry.base + entry.len > end)
+		{
+			let entry_base = cmp::max(entry.base, end);
+			self.push(entry_base, entry.base + entry.len - entry_base, entry.kind);
+		}
+	}
+
+	/// Appends a region, merging it with the last entry if possible.
+	///
+	/// If the map is full, the last entry is extended to cover the region and reserved.
+	fn push(&mut self, base: u64, len: u64, kind: MemoryKind) {
+		if let Some(last) = self.entries[..self.len].last_mut() {
+			if last.kind == kind && last.base + last.len == base {
+				last.len += len;
+				return;
+			}
+
+			if self.len == MEMORY_MAP_CAPACITY {
+				last.kind = MemoryKind::Reserved;
+				last.len = base + len - last.base;
+				self.truncated = true;
+				return;
+			}
+		}
+
+		self.entries[self.len] = MemoryMapEntry { base, len, kind };
+		self.len += 1;
+	}
+}
@@ -1,32 +1,56 @@
 pub mod bootinfo;
+pub mod memorymap;
 pub mod paging;
 pub mod physicalmem;
 
 pub use self::bootinfo::*;
 
-use core::ptr::{copy, write_bytes};
-use core::{cmp, mem, slice};
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid_count, __get_cpuid_max};
+use core::mem::MaybeUninit;
+use core::ops::Range;
+use core::ptr::{copy, copy_nonoverlapping, write_bytes};
+use core::{cmp, mem, ptr, slice};
 
-use multiboot::information::{MemoryManagement, Multiboot, PAddr};
+use multiboot::information::{MemoryManagement, MemoryType, Multiboot, PAddr};
 use uart_16550::SerialPort;
 
+use memorymap::{MemoryKind, MemoryMap};
 use paging::{BasePageSize, LargePageSize, PageSize, PageTableEntryFlags};
 
 extern "C" {
 	static mb_info: usize;
+	static kernel_start: u8;
 	static kernel_end: u8;
 }
 
 // CONSTANTS
 pub const ELF_ARCH: u16 = goblin::elf::header::EM_X86_64;
 pub const R_RELATIVE: u32 = goblin::elf::reloc::R_X86_64_RELATIVE;
+pub const R_ABS64: u32 = goblin::elf::reloc::R_X86_64_64;
+pub const R_GLOB_DAT: u32 = goblin::elf::reloc::R_X86_64_GLOB_DAT;
+pub const R_JUMP_SLOT: u32 = goblin::elf::reloc::R_X86_64_JUMP_SLOT;
+pub const R_IRELATIVE: u32 = goblin::elf::reloc::R_X86_64_IRELATIVE;
+/// Required alignment of the kernel entry point (x86-64 instructions are not aligned)
+pub const ENTRY_ALIGN: u64 = 1;
 
 const KERNEL_STACK_SIZE: u64 = 32_768;
 const SERIAL_IO_PORT: u16 = 0x3F8;
+/// Minimum number of bytes for which `copy_memory` uses `rep movsb`
+const FAST_COPY_THRESHOLD: usize = 0x1000;
+/// Size of the Multiboot information structure (`multiboot_info`) in bytes
+const MULTIBOOT_INFO_SIZE: u64 = 116;
+/// Size of an entry of the Multiboot module list in bytes
+const MULTIBOOT_MODULE_SIZE: u64 = 16;
+/// Flag of the Multiboot information that marks the module list as valid
+const MULTIBOOT_INFO_MODS: u32 = 1 << 3;
+/// Flag of the Multiboot information that marks the memory map as valid
+const MULTIBOOT_INFO_MEM_MAP: u32 = 1 << 6;
 
 // VARIABLES
 static mut COM1: SerialPort = unsafe { SerialPort::new(SERIAL_IO_PORT) };
 pub static mut BOOT_INFO: BootInfo = BootInfo::new();
+static mut MEMORY_MAP: MemoryMap = MemoryMap::new();
 
 struct Mem;
 static mut MEM: Mem = Mem;
@@ -54,16 +78,62 @@ pub fn message_output_init() {
 	unsafe { COM1.init() };
 }
 
+// Unit tests print to the host's standard output instead, see `Console`.
+#[cfg_attr(test, allow(dead_code))]
 pub fn output_message_byte(byte: u8) {
 	unsafe { COM1.send(byte) };
 }
 
+/// Returns whether the CPU supports Enhanced REP MOVSB/STOSB (ERMS).
+fn has_erms() -> bool {
+	unsafe {
+		// CPUID.(EAX=07H, ECX=0H):EBX.ERMS[bit 9]
+		__get_cpuid_max(0).0 >= 7 && __cpuid_count(7, 0).ebx & (1 << 9) != 0
+	}
+}
+
+/// Copies `src` into `dst`, which must be of the same length.
+///
+/// Large copies are done via `rep movsb` if the CPU supports ERMS, which is faster than a generic copy loop.
+pub fn copy_memory(dst: &mut [MaybeUninit<u8>], src: &[u8]) {
+	assert_eq!(dst.len(), src.len());
+
+	if src.len() >= FAST_COPY_THRESHOLD && has_erms() {
+		unsafe {
+			asm!(
+				"rep movsb",
+				inout("rcx") src.len() => _,
+				inout("rdi") dst.as_mut_ptr() => _,
+				inout("rsi") src.as_ptr() => _,
+				options(nostack, preserves_flags),
+			);
+		}
+	} else {
+		// `dst` is borrowed mutably, so it cannot overlap with `src`.
+		unsafe {
+			copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr().cast::<u8>(), src.len());
+		}
+	}
+}
+
+/// Zeroes `dst`.
+pub fn zero_memory(dst: &mut [MaybeUninit<u8>]) {
+	unsafe {
+		write_bytes(dst.as_mut_ptr(), 0, dst.len());
+	}
+}
+
 pub unsafe fn find_kernel() -> &'static [u8] {
+	// Program the Page Attribute Table before creating any mappings that depend on it.
+	paging::init_pat();
+	paging::init_linear_address_width();
+
 	// Identity-map the Multiboot information.
 	assert!(mb_info > 0, "Could not find Multiboot information");
 	loaderlog!("Found Multiboot information at {:#x}", mb_info);
 	let page_address = align_down!(mb_info, BasePageSize::SIZE);
-	paging::map::<BasePageSize>(page_address, page_address, 1, PageTableEntryFlags::WRITABLE);
+	paging::map::<BasePageSize>(page_address, page_address, 1, PageTableEntryFlags::WRITABLE)
+		.unwrap();
 
 	// Load the Multiboot information and identity-map the modules information.
 	let multiboot = Multiboot::from_ptr(mb_info as u64, &mut MEM).unwrap();
@@ -74,37 +144,85 @@ pub unsafe fn find_kernel() -> &'static [u8] {
 		.expect("Could not find first map address")
 		.start as usize;
 	let page_address = align_down!(modules_address, BasePageSize::SIZE);
-	paging::map::<BasePageSize>(page_address, page_address, 1, PageTableEntryFlags::empty());
+	paging::map::<BasePageSize>(page_address, page_address, 1, PageTableEntryFlags::empty())
+		.unwrap();
 
 	// Iterate through all modules.
-	// Collect the start address of the first module and the highest end address of all modules.
+	// The first module is the kernel, further modules (e.g., the initial ramdisk) follow it in memory.
+	// Collect the range of the first module and the highest end address of all modules.
 	let modules = multiboot.modules().unwrap();
 	let mut found_module = false;
 	let mut start_address = 0;
+	let mut elf_end = 0;
 	let mut end_address = 0;
 
 	for m in modules {
-		found_module = true;
-
-		if start_address == 0 {
+		if !found_module {
 			start_address = m.start as usize;
+			elf_end = m.end as usize;
 		}
+		found_module = true;
 
 		if m.end as usize > end_address {
 			end_address = m.end as usize;
 		}
 	}
 
-	loaderlog!("Found module: [{:#x} - {:#x}]", start_address, end_address);
+	loaderlog!("Found module: [{:#x} - {:#x}]", start_address, elf_end);
 	let elf_start = start_address;
-	let elf_len = end_address - start_address;
-	loaderlog!("Module length: {:#x}", elf_len);
+	let elf_len = elf_end - start_address;
+	loaderlog!(debug, "Module length: {:#x}", elf_len);
 
 	let free_memory_address = align_up!(end_address, LargePageSize::SIZE);
 	// TODO: Workaround for https://github.com/hermitcore/rusty-loader/issues/96
 	let free_memory_address = cmp::max(free_memory_address, 0x800000);
-	// Memory after the highest end address is unused and available for the physical memory manager.
-	physicalmem::init(free_memory_address);
+	// Available memory after the highest end address is unused and handed to the physical memory manager.
+	let free_memory_regions = || {
+		multiboot
+			.memory_regions()
+			.into_iter()
+			.flatten()
+			.filter(|region| matches!(region.memory_type(), MemoryType::Available))
+			.map(|region| {
+				let start = region.base_address() as usize;
+				let end = start + region.length() as usize;
+				let start = cmp::max(align_up!(start, BasePageSize::SIZE), free_memory_address);
+				start..align_down!(end, BasePageSize::SIZE)
+			})
+	};
+	if multiboot.memory_regions().is_some() {
+		// The firmware may list its regions in any order and with overlaps,
+		// so they are added by ascending address and without the parts that have already been added.
+		let mut next_address = free_memory_address;
+		while let Some(region) = free_memory_regions()
+			.map(|region| cmp::max(region.start, next_address)..region.end)
+			.filter(|region| !region.is_empty())
+			.min_by_key(|region| region.start)
+		{
+			loaderlog!(
+				debug,
+				"Free memory: [{:#x} - {:#x}]",
+				region.start,
+				region.end
+			);
+			physicalmem::add_region(region.start, region.end);
+			next_address = region.end;
+		}
+		assert!(
+			next_address > free_memory_address,
+			"Could not find available memory after the kernel"
+		);
+	} else {
+		// Without a memory map from the firmware, all memory after the highest end address is assumed to be free.
+		let free_memory_end = align_down!(usize::MAX, BasePageSize::SIZE);
+		loaderlog!(
+			debug,
+			"Free memory: [{:#x} - {:#x}]",
+			free_memory_address,
+			free_memory_end
+		);
+		physicalmem::add_region(free_memory_address, free_memory_end);
+	}
 
 	// Identity-map the ELF header of the first module.
 	assert!(
@@ -117,6 +235,7 @@ pub unsafe fn find_kernel() -> &'static [u8] {
 	let counter =
 		(align_up!(start_address, LargePageSize::SIZE) - page_address) / BasePageSize::SIZE;
 	loaderlog!(
+		debug,
 		"Map {} pages at {:#x} (page size {} KByte)",
 		counter,
 		page_address,
@@ -127,35 +246,65 @@ pub unsafe fn find_kernel() -> &'static [u8] {
 		page_address,
 		counter,
 		PageTableEntryFlags::empty(),
-	);
+	)
+	.unwrap();
 
-	// map also the rest of the module
+	// map also the rest of the module and the following modules
 	let address = align_up!(start_address, LargePageSize::SIZE);
 	let counter = (align_up!(end_address, LargePageSize::SIZE) - address) / LargePageSize::SIZE;
 	if counter > 0 {
 		loaderlog!(
+			debug,
 			"Map {} pages at {:#x} (page size {} KByte)",
 			counter,
 			address,
 			LargePageSize::SIZE / 1024
 		);
-		paging::map::<LargePageSize>(address, address, counter, PageTableEntryFlags::WRITABLE);
+		paging::map::<LargePageSize>(address, address, counter, PageTableEntryFlags::WRITABLE)
+			.unwrap();
 	}
 
 	slice::from_raw_parts(elf_start as *const u8, elf_len)
 }
 
+/// Returns the initial ramdisk, which is passed as the second Multiboot module, if any.
+///
+/// All modules have already been identity-mapped by [`find_kernel`].
+pub unsafe fn find_initrd() -> Option<&'static [u8]> {
+	let multiboot = Multiboot::from_ptr(mb_info as u64, &mut MEM).unwrap();
+	let module = multiboot.modules()?.nth(1)?;
+	loaderlog!(
+		"Found initial ramdisk module: [{:#x} - {:#x}]",
+		module.start,
+		module.end
+	);
+
+	let start = module.start as usize;
+	let len = (module.end - module.start) as usize;
+	Some(slice::from_raw_parts(start as *const u8, len))
+}
+
 pub unsafe fn boot_kernel(
 	elf_address: Option<u64>,
 	virtual_address: u64,
 	mem_size: u64,
 	entry_point: u64,
+	stack_executable: bool,
 ) -> ! {
 	let new_addr = match elf_address {
 		Some(addr) => {
 			if virtual_address != addr {
 				loaderlog!("Copy kernel from {:#x} to {:#x}", virtual_address, addr);
 
+				// The fixed address of the kernel cannot be honored if the kernel would overwrite the loader.
+				let loader = &kernel_start as *const u8 as u64..&kernel_end as *const u8 as u64;
+				assert!(
+					addr + mem_size <= loader.start || loader.end <= addr,
+					"Kernel at its fixed address {:#x} would overlap the loader at {:#x?}",
+					addr,
+					loader
+				);
+
 				// copy app to the new start address
 				copy(
 					virtual_address as *const u8,
@@ -169,23 +318,43 @@ pub unsafe fn boot_kernel(
 		None => virtual_address,
 	};
 
+	// A miscomputed base could place parts of the kernel in the non-canonical hole or on both sides of it.
+	let kernel_range = new_addr as usize..(new_addr + mem_size) as usize;
+	assert!(
+		paging::is_canonical_range(kernel_range.clone()),
+		"Kernel at {kernel_range:#x?} does not lie in canonical address space"
+	);
+	assert!(
+		paging::is_canonical_range(entry_point as usize..entry_point as usize + 1),
+		"Kernel entry point {entry_point:#x} does not lie in canonical address space"
+	);
+
 	// Supply the parameters to the HermitCore application.
-	BOOT_INFO.base = new_addr;
-	BOOT_INFO.image_size = mem_size;
-	BOOT_INFO.mb_info = mb_info as u64;
+	let mut boot_info = BootInfoBuilder::new(BOOT_INFO);
+	boot_info
+		.image(new_addr, mem_size)
+		.multiboot(mb_info as u64);
 
 	let multiboot = Multiboot::from_ptr(mb_info as u64, &mut MEM).unwrap();
-	if let Some(cmdline) = multiboot.command_line() {
-		let address = cmdline.as_ptr();
-
-		// Identity-map the command line.
-		let page_address = align_down!(address as usize, BasePageSize::SIZE);
-		paging::map::<BasePageSize>(page_address, page_address, 1, PageTableEntryFlags::empty());
-
-		//let cmdline = multiboot.command_line().unwrap();
-		BOOT_INFO.cmdline = address as u64;
-		BOOT_INFO.cmdsize = cmdline.len() as u64;
-	}
+	let (cmdline, cmdsize) = match multiboot.command_line() {
+		Some(cmdline) => {
+			let address = cmdline.as_ptr();
+
+			// Identity-map the command line.
+			let page_address = align_down!(address as usize, BasePageSize::SIZE);
+			paging::map::<BasePageSize>(
+				page_address,
+				page_address,
+				1,
+				PageTableEntryFlags::empty(),
+			)
+			.unwrap();
+
+			(address as u64, cmdline.len() as u64)
+		}
+		None => (0, 0),
+	};
+	boot_info.cmdline(cmdline, cmdsize);
 
 	// determine boot stack address
 	let mut new_stack = align_up!(&kernel_end as *const u8 as usize, BasePageSize::SIZE);
@@ -197,22 +366,29 @@ pub unsafe fn boot_kernel(
 		);
 	}
 
-	if new_stack + KERNEL_STACK_SIZE as usize > BOOT_INFO.cmdline as usize {
-		new_stack = align_up!(
-			(BOOT_INFO.cmdline + BOOT_INFO.cmdsize) as usize,
-			BasePageSize::SIZE
-		);
+	if new_stack + KERNEL_STACK_SIZE as usize > cmdline as usize {
+		new_stack = align_up!((cmdline + cmdsize) as usize, BasePageSize::SIZE);
 	}
 
-	BOOT_INFO.current_stack_address = new_stack.try_into().unwrap();
+	let stack_range = new_stack..new_stack + KERNEL_STACK_SIZE as usize;
+	assert!(
+		paging::is_canonical_range(stack_range.clone()),
+		"Stack at {stack_range:#x?} does not lie in canonical address space"
+	);
+	boot_info.stack(new_stack.try_into().unwrap());
 
 	// map stack in the address space
+	let mut stack_flags = PageTableEntryFlags::WRITABLE;
+	if !stack_executable && paging::enable_execute_disable() {
+		stack_flags.insert(PageTableEntryFlags::EXECUTE_DISABLE);
+	}
 	paging::map::<BasePageSize>(
 		new_stack,
 		new_stack,
 		KERNEL_STACK_SIZE as usize / BasePageSize::SIZE,
-		PageTableEntryFlags::WRITABLE,
-	);
+		stack_flags,
+	)
+	.unwrap();
 
 	// clear stack
 	write_bytes(
@@ -221,33 +397,321 @@ pub unsafe fn boot_kernel(
 		KERNEL_STACK_SIZE.try_into().unwrap(),
 	);
 
-	loaderlog!("BootInfo located at {:#x}", &BOOT_INFO as *const _ as u64);
+	init_memory_map(&multiboot, &mut boot_info);
+	BOOT_INFO = boot_info.finish();
+	loaderlog!(
+		"Memory map with {} entries located at {:#x}",
+		BOOT_INFO.memory_map_len,
+		BOOT_INFO.memory_map
+	);
+
+	loaderlog!(
+		debug,
+		"BootInfo located at {:#x}",
+		&BOOT_INFO as *const _ as u64
+	);
+	loaderlog!(
+		debug,
+		"Root page table located at {:#x}",
+		paging::current_pml4_phys()
+	);
 	//loaderlog!("BootInfo {:?}", BOOT_INFO);
-	loaderlog!("Use stack address {:#x}", BOOT_INFO.current_stack_address);
+	loaderlog!(
+		debug,
+		"Use stack address {:#x}",
+		BOOT_INFO.current_stack_address
+	);
+
+	let physicalmem::Stats {
+		used,
+		alignment_waste,
+		discarded,
+	} = physicalmem::stats();
+	loaderlog!(
+		"Used {used:#x} B of physical memory ({alignment_waste:#x} B lost to alignment, {discarded:#x} B discarded)"
+	);
 
 	// Jump to the kernel entry point and provide the Multiboot information to it.
 	loaderlog!(
 		"Jumping to HermitCore Application Entry Point at {:#x}",
 		entry_point
 	);
-	let func: extern "C" fn(boot_info: &'static mut BootInfo) -> ! =
-		core::mem::transmute(entry_point);
+	PreparedKernel {
+		entry_point,
+		stack_top: new_stack as u64 + KERNEL_STACK_SIZE,
+		address_space: None,
+	}
+	.enter()
+}
+
+/// A loaded kernel that is ready to be entered.
+pub struct PreparedKernel {
+	/// Virtual address of the kernel's entry point.
+	pub entry_point: u64,
+
+	/// Initial stack pointer, which must be aligned to 16 bytes.
+	pub stack_top: u64,
+
+	/// Address space to switch to before entering the kernel, if different from the active one.
+	pub address_space: Option<paging::AddressSpace>,
+}
+
+impl PreparedKernel {
+	/// Hands control over to the kernel.
+	///
+	/// This switches to the kernel's address space, sets up the stack, and calls the entry point according to the
+	/// System V ABI with a pointer to [`BOOT_INFO`] as its only argument (in `rdi`).
+	///
+	/// # Safety
+	///
+	/// The kernel has to be completely loaded and [`BOOT_INFO`] must be populated.
+	/// See [`paging::switch_address_space`] for the requirements on the address space.
+	pub unsafe fn enter(self) -> ! {
+		assert_eq!(
+			self.stack_top % 16,
+			0,
+			"Stack pointer {:#x} is not aligned to 16 bytes",
+			self.stack_top
+		);
+
+		if let Some(address_space) = &self.address_space {
+			paging::switch_address_space(address_space);
+		}
+
+		// Enter the kernel according to the System V AMD64 ABI, which passes the pointer to the boot information
+		// as the first argument in rdi.
+		// The call pushes the return address, which leaves the stack aligned as if the kernel had been called
+		// from a regular function.
+		asm!(
+			"mov rsp, {stack_top}",
+			"xor ebp, ebp",
+			"call {entry_point}",
+			"ud2",
+			stack_top = in(reg) self.stack_top,
+			entry_point = in(reg) self.entry_point,
+			in("rdi") &BOOT_INFO as *const BootInfo,
+			options(noreturn),
+		)
+	}
+}
+
+/// Builds the memory map for the kernel and supplies it via `boot_info`.
+///
+/// Must be called after all other fields of `boot_info` have been set.
+unsafe fn init_memory_map(multiboot: &Multiboot<'_, '_>, boot_info: &mut BootInfoBuilder) {
+	fn insert_pages(memory_map: &mut MemoryMap, address: u64, len: u64, kind: MemoryKind) {
+		let start = align_down!(address, BasePageSize::SIZE as u64);
+		let end = align_up!(address + len, BasePageSize::SIZE as u64);
+		memory_map.insert(start, end - start, kind);
+	}
+
+	let memory_map = &mut MEMORY_MAP;
+
+	// Start with the memory map from the firmware.
+	if let Some(memory_regions) = multiboot.memory_regions() {
+		for region in memory_regions {
+			let kind = match region.memory_type() {
+				MemoryType::Available => MemoryKind::Usable,
+				_ => MemoryKind::Reserved,
+			};
+			memory_map.insert(region.base_address(), region.length(), kind);
+		}
+	}
+
+	// Reserve everything the loader has used.
+	let loader_start = &kernel_start as *const u8 as u64;
+	let loader_end = &kernel_end as *const u8 as u64;
+	insert_pages(
+		memory_map,
+		loader_start,
+		loader_end - loader_start,
+		MemoryKind::Loader,
+	);
+	for allocated_range in physicalmem::allocated_ranges() {
+		memory_map.insert(
+			allocated_range.start as u64,
+			(allocated_range.end - allocated_range.start) as u64,
+			MemoryKind::Loader,
+		);
+	}
+	let info = boot_info.boot_info();
+	memory_map.insert(
+		info.current_stack_address,
+		KERNEL_STACK_SIZE,
+		MemoryKind::Loader,
+	);
+
+	insert_pages(memory_map, info.base, info.image_size, MemoryKind::Kernel);
+
+	insert_pages(
+		memory_map,
+		&BOOT_INFO as *const _ as u64,
+		mem::size_of::<BootInfo>() as u64,
+		MemoryKind::BootInfo,
+	);
+	for (address, len) in multiboot_structures() {
+		insert_pages(memory_map, address, len, MemoryKind::BootInfo);
+	}
+	insert_pages(memory_map, info.cmdline, info.cmdsize, MemoryKind::BootInfo);
+	insert_pages(
+		memory_map,
+		info.ramdisk_image,
+		info.ramdisk_len,
+		MemoryKind::Ramdisk,
+	);
+	insert_pages(
+		memory_map,
+		memory_map as *const MemoryMap as u64,
+		mem::size_of::<MemoryMap>() as u64,
+		MemoryKind::BootInfo,
+	);
+
+	if memory_map.is_truncated() {
+		loaderlog!("WARNING: memory map is full, some regions are reported as reserved");
+	}
+
+	let entries = memory_map.entries();
+	boot_info.memory_map(entries.as_ptr() as u64, entries.len() as u64);
+}
 
-	func(&mut BOOT_INFO);
+/// Returns the start addresses and sizes of the Multiboot information structure and of the module list and the
+/// memory map it refers to.
+///
+/// Missing structures are empty.
+unsafe fn multiboot_structures() -> [(u64, u64); 3] {
+	let field = |offset: usize| ptr::read_unaligned((mb_info + offset) as *const u32);
+	let flags = field(0);
+
+	let modules = if flags & MULTIBOOT_INFO_MODS != 0 {
+		let mods_count = u64::from(field(20));
+		let mods_addr = u64::from(field(24));
+		(mods_addr, mods_count * MULTIBOOT_MODULE_SIZE)
+	} else {
+		(0, 0)
+	};
+	let mmap = if flags & MULTIBOOT_INFO_MEM_MAP != 0 {
+		let mmap_length = u64::from(field(44));
+		let mmap_addr = u64::from(field(48));
+		(mmap_addr, mmap_length)
+	} else {
+		(0, 0)
+	};
 
-	// we never reach this point
+	[(mb_info as u64, MULTIBOOT_INFO_SIZE), modules, mmap]
 }
 
 unsafe fn map_memory(address: usize, memory_size: usize) -> usize {
 	let address = align_up!(address, LargePageSize::SIZE);
 	let page_count = align_up!(memory_size, LargePageSize::SIZE) / LargePageSize::SIZE;
 
-	paging::map::<LargePageSize>(address, address, page_count, PageTableEntryFlags::WRITABLE);
+	paging::map::<LargePageSize>(address, address, page_count, PageTableEntryFlags::WRITABLE)
+		.unwrap();
 
 	address
 }
 
-pub unsafe fn get_memory(memory_size: u64) -> u64 {
-	let address = physicalmem::allocate(align_up!(memory_size as usize, LargePageSize::SIZE));
+/// Allocates and identity-maps memory for loading the kernel.
+pub fn allocate_kernel_memory(size: usize, align: usize) -> &'static mut [MaybeUninit<u8>] {
+	unsafe {
+		let address = get_memory(size as u64, align as u64);
+		slice::from_raw_parts_mut(address as *mut MaybeUninit<u8>, size)
+	}
+}
+
+/// A saved state of the physical memory allocations, see [`release_kernel_memory`].
+pub struct KernelMemoryMark(physicalmem::Watermark);
+
+/// Saves the state of the physical memory allocations before allocating memory for a kernel.
+pub fn mark_kernel_memory() -> KernelMemoryMark {
+	KernelMemoryMark(physicalmem::mark())
+}
+
+/// Frees `memory` from [`allocate_kernel_memory`] and everything else allocated since `mark`,
+/// e.g., after loading a kernel into it failed.
+///
+/// The memory is unmapped first, so the page tables created for mapping it are freed as well.
+pub fn release_kernel_memory(mark: KernelMemoryMark, memory: &'static mut [MaybeUninit<u8>]) {
+	let address = memory.as_ptr() as usize;
+	let page_count = align_up!(memory.len(), LargePageSize::SIZE) / LargePageSize::SIZE;
+	paging::unmap::<LargePageSize>(address, page_count);
+	physicalmem::release(mark.0);
+}
+
+/// Makes the given page-aligned range of the loaded kernel read-only, such as its RELRO region after relocating it.
+///
+/// The kernel memory is mapped with 2 MiB pages, which are split into 4 KiB pages first.
+pub fn write_protect(range: Range<u64>) {
+	let range = range.start as usize..range.end as usize;
+	if range.is_empty() {
+		return;
+	}
+
+	for large_page in
+		(align_down!(range.start, LargePageSize::SIZE)..range.end).step_by(LargePageSize::SIZE)
+	{
+		paging::split_large_page(large_page);
+	}
+
+	let mut flags = paging::flags_of(range.start).unwrap();
+	flags.remove(PageTableEntryFlags::WRITABLE);
+	paging::protect::<BasePageSize>(range.start, range.len() / BasePageSize::SIZE, flags);
+}
+
+/// Maps the frames of `src` at `dst` instead of copying them, such as for read-only kernel segments.
+///
+/// Returns the length of the shared part, which covers all whole pages of `src` if both `src` and `dst` are
+/// page-aligned and is empty otherwise. The caller has to copy the rest.
+/// The shared pages are mapped read-only and only executable if `p_flags` contains `PF_X`.
+/// The frames previously backing them are freed.
+pub fn share_memory(dst: &mut [MaybeUninit<u8>], src: &[u8], p_flags: u32) -> usize {
+	assert_eq!(dst.len(), src.len());
+
+	let dst_address = dst.as_mut_ptr() as usize;
+	let src_address = src.as_ptr() as usize;
+	if dst_address % BasePageSize::SIZE != 0 || src_address % BasePageSize::SIZE != 0 {
+		return 0;
+	}
+	let len = align_down!(src.len(), BasePageSize::SIZE);
+	if len == 0 {
+		return 0;
+	}
+
+	for large_page in (align_down!(dst_address, LargePageSize::SIZE)..dst_address + len)
+		.step_by(LargePageSize::SIZE)
+	{
+		paging::split_large_page(large_page);
+	}
+
+	let mut flags = PageTableEntryFlags::from_segment_flags(p_flags);
+	flags.remove(PageTableEntryFlags::WRITABLE);
+	if !paging::enable_execute_disable() {
+		flags.remove(PageTableEntryFlags::EXECUTE_DISABLE);
+	}
+	let frames = (src_address..src_address + len)
+		.step_by(BasePageSize::SIZE)
+		.map(|page| paging::translate(page).unwrap());
+	paging::map_frames::<BasePageSize, _>(dst_address, frames, flags).unwrap();
+
+	// The kernel memory is identity-mapped, so its virtual addresses are the physical addresses of its frames.
+	physicalmem::deallocate(dst_address, len);
+	len
+}
+
+/// Allocates and identity-maps memory for temporary data, such as a decompressed kernel image.
+pub fn allocate_scratch_memory(size: usize) -> Option<&'static mut [MaybeUninit<u8>]> {
+	unsafe {
+		let address = get_memory(size as u64, BasePageSize::SIZE as u64);
+		Some(slice::from_raw_parts_mut(
+			address as *mut MaybeUninit<u8>,
+			size,
+		))
+	}
+}
+
+pub unsafe fn get_memory(memory_size: u64, align: u64) -> u64 {
+	// The kernel is mapped using large pages, so we need at least their alignment.
+	let align = cmp::max(align as usize, LargePageSize::SIZE);
+	let address =
+		physicalmem::allocate_aligned(align_up!(memory_size as usize, LargePageSize::SIZE), align);
 	map_memory(address, memory_size as usize) as u64
 }
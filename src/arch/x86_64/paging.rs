@@ -1,10 +1,40 @@
 use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __get_cpuid_max};
+use core::cmp;
+use core::fmt;
 use core::marker::PhantomData;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use goblin::elf64::program_header::{PF_W, PF_X};
 
 use crate::arch::x86_64::physicalmem;
 
-/// Pointer to the root page table (PML4)
-const PML4_ADDRESS: *mut PageTable<PML4> = 0xFFFF_FFFF_FFFF_F000 as *mut PageTable<PML4>;
+/// The root page table level, which also holds the recursive mapping.
+#[cfg(not(feature = "la57"))]
+type RootLevel = PML4;
+
+/// The root page table level, which also holds the recursive mapping.
+#[cfg(feature = "la57")]
+type RootLevel = PML5;
+
+/// Pointer to the root page table (PML4, or PML5 with 5-level paging)
+const ROOT_PAGETABLE_ADDRESS: *mut PageTable<RootLevel> =
+	0xFFFF_FFFF_FFFF_F000 as *mut PageTable<RootLevel>;
+
+/// Index of the entry in the root page table that refers to the root page table itself.
+const RECURSIVE_INDEX: usize = 511;
+
+/// End of the lower half of the canonical address space, where the non-canonical hole begins.
+const LOWER_HALF_END: usize = PageTable::<RootLevel>::ENTRY_SIZE << (PAGE_MAP_BITS - 1);
+
+/// Most significant bit of a linear address that is translated by the paging mode (47 with 4-level paging, 56 with 5-level paging).
+const MAX_SIGN_BIT: usize = LOWER_HALF_END.trailing_zeros() as usize;
+
+/// Most significant implemented bit of a linear address, which bits 63 through it have to replicate.
+///
+/// Set once during paging initialization by [`init_linear_address_width`] or [`set_linear_address_width`].
+static SIGN_BIT: AtomicUsize = AtomicUsize::new(MAX_SIGN_BIT);
 
 /// Number of Offset bits of a virtual address for a 4 KiB page, which are shifted away to get its Page Frame Number (PFN).
 const PAGE_BITS: usize = 12;
@@ -15,6 +45,21 @@ const PAGE_MAP_BITS: usize = 9;
 /// A mask where PAGE_MAP_BITS are set to calculate a table index.
 const PAGE_MAP_MASK: usize = 0x1FF;
 
+/// A mask where the bits of a 4 KiB aligned physical address are set (bits 12 through 51).
+const PHYSICAL_ADDRESS_MASK: usize = 0x000F_FFFF_FFFF_F000;
+
+/// Number of page table levels in the hierarchy (PML4, PDPT, PDT, PGT).
+///
+/// Every table walk is bounded by this depth.
+#[cfg(not(feature = "la57"))]
+const PAGE_LEVELS: usize = 4;
+
+/// Number of page table levels in the hierarchy (PML5, PML4, PDPT, PDT, PGT).
+///
+/// Every table walk is bounded by this depth.
+#[cfg(feature = "la57")]
+const PAGE_LEVELS: usize = 5;
+
 bitflags::bitflags! {
 	/// Possible flags for an entry in either table (PML4, PDPT, PDT, PGT)
 	///
@@ -45,19 +90,101 @@ bitflags::bitflags! {
 		/// Only for page entries in PDPT or PDT: Set if this entry references a 1 GiB (PDPT) or 2 MiB (PDT) page.
 		const HUGE_PAGE = 1 << 7;
 
+		/// Only for page entries in PGT: Selects the upper half of the Page Attribute Table (PAT) together with
+		/// WRITE_THROUGH and CACHE_DISABLE. Shares its bit with HUGE_PAGE.
+		const PAT = 1 << 7;
+
+		/// Only for page entries in PDPT or PDT: Selects the upper half of the Page Attribute Table (PAT)
+		/// together with WRITE_THROUGH and CACHE_DISABLE.
+		const PAT_HUGE = 1 << 12;
+
 		/// Only for page entries: Set if this address translation is global for all tasks and does not need to
 		/// be flushed from the TLB when CR3 is reset.
 		const GLOBAL = 1 << 8;
 
+		/// Ignored by the CPU: Marks a non-present guard page installed by map_guard_page.
+		const GUARD = 1 << 9;
+
 		/// Set if code execution shall be disabled for memory referenced by this entry.
 		const EXECUTE_DISABLE = 1 << 63;
 	}
 }
 
+/// Model-specific register holding the Extended Feature Enables (IA32_EFER).
+const IA32_EFER: u32 = 0xC000_0080;
+
+/// Execute-Disable Bit Enable (IA32_EFER.NXE).
+const EFER_NXE: u32 = 1 << 11;
+
+/// Model-specific register holding the Page Attribute Table (IA32_PAT).
+const IA32_PAT: u32 = 0x277;
+
+/// Page Attribute Table programmed by [`init_pat`].
+///
+/// The lower half (PA0 through PA3) keeps the power-on layout (WB, WT, UC-, UC),
+/// so entries without the PAT flag behave as usual.
+/// PA4 is changed to Write-Combining (WC) and the rest is kept (WT, UC-, UC).
+const PAT_LAYOUT: u64 = 0x0007_0401_0007_0406;
+
+/// An error that occurred while mapping pages.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MapError {
+	/// There is not enough physical memory left for the page tables required by the mapping.
+	OutOfMemory,
+
+	/// A page of the mapping is already mapped.
+	AlreadyMapped,
+}
+
+/// An allocator for the frames of page tables that have to be created while mapping pages.
+///
+/// [`physicalmem::FrameAlloc`] allocates from the physical memory manager and is used by [`map`].
+pub trait PageTableAllocator {
+	/// Returns the physical address of an unused 4 KiB frame, or None if there is no memory left.
+	///
+	/// The frame does not need to be mapped, since new page tables are zeroed through the recursive mapping.
+	fn allocate_frame(&mut self) -> Option<usize>;
+}
+
 impl PageTableEntryFlags {
 	/// An empty set of flags for unused/zeroed table entries.
 	/// Needed as long as empty() is no const function.
 	const BLANK: PageTableEntryFlags = PageTableEntryFlags { bits: 0 };
+
+	/// Returns the flags for mapping pages of size S with write-combining caching, e.g., for a framebuffer.
+	///
+	/// This selects PA4 of the Page Attribute Table, which requires [`init_pat`] to have been called.
+	#[allow(dead_code)]
+	pub fn write_combining<S: PageSize>() -> Self {
+		if S::MAP_LEVEL == 0 {
+			PageTableEntryFlags::PAT
+		} else {
+			PageTableEntryFlags::PAT_HUGE
+		}
+	}
+
+	/// Returns the flags for mapping uncacheable memory, e.g., for memory-mapped I/O.
+	///
+	/// This selects PA3 of the Page Attribute Table, which is UC in both the power-on and our layout.
+	#[allow(dead_code)]
+	pub fn uncacheable() -> Self {
+		PageTableEntryFlags::WRITE_THROUGH | PageTableEntryFlags::CACHE_DISABLE
+	}
+
+	/// Returns the flags for mapping an ELF segment with the given permissions (`p_flags`).
+	///
+	/// Only segments with `PF_W` are writable and only segments with `PF_X` are executable.
+	/// Note that EXECUTE_DISABLE requires EFER.NXE to be set.
+	pub fn from_segment_flags(p_flags: u32) -> Self {
+		let mut flags = PageTableEntryFlags::empty();
+		if p_flags & PF_W != 0 {
+			flags.insert(PageTableEntryFlags::WRITABLE);
+		}
+		if p_flags & PF_X == 0 {
+			flags.insert(PageTableEntryFlags::EXECUTE_DISABLE);
+		}
+		flags
+	}
 }
 
 /// An entry in either table (PML4, PDPT, PDT, PGT)
@@ -73,37 +200,89 @@ impl PageTableEntry {
 		(self.physical_address_and_flags & PageTableEntryFlags::PRESENT.bits()) != 0
 	}
 
+	/// Returns whether this entry is a guard page installed by [`map_guard_page`].
+	fn is_guard(&self) -> bool {
+		!self.is_present()
+			&& (self.physical_address_and_flags & PageTableEntryFlags::GUARD.bits()) != 0
+	}
+
+	/// Returns whether this entry references a 1 GiB (PDPT) or 2 MiB (PDT) page instead of a subtable.
+	fn is_huge(&self) -> bool {
+		(self.physical_address_and_flags & PageTableEntryFlags::HUGE_PAGE.bits()) != 0
+	}
+
+	/// Returns the physical address this entry refers to.
+	fn address(&self) -> usize {
+		self.physical_address_and_flags & PHYSICAL_ADDRESS_MASK
+	}
+
+	/// Returns the flags of this entry.
+	fn flags(&self) -> PageTableEntryFlags {
+		PageTableEntryFlags::from_bits_truncate(
+			self.physical_address_and_flags & !PHYSICAL_ADDRESS_MASK,
+		)
+	}
+
+	/// Returns the physical address of the page this entry maps, where `level` is the level of its table.
+	///
+	/// Entries of 1 GiB and 2 MiB pages store PAT_HUGE in bit 12, which is not part of their address.
+	fn page_address(&self, level: usize) -> usize {
+		if level > 0 {
+			self.address() & !PageTableEntryFlags::PAT_HUGE.bits()
+		} else {
+			self.address()
+		}
+	}
+
+	/// Returns the flags of the page this entry maps, where `level` is the level of its table.
+	///
+	/// Unlike [`Self::flags`], this includes PAT_HUGE for entries of 1 GiB and 2 MiB pages.
+	fn page_flags(&self, level: usize) -> PageTableEntryFlags {
+		let mut flags = self.flags();
+		if level > 0 {
+			flags.set(
+				PageTableEntryFlags::PAT_HUGE,
+				self.physical_address_and_flags & PageTableEntryFlags::PAT_HUGE.bits() != 0,
+			);
+		}
+		flags
+	}
+
+	/// Marks this entry as unused, clearing its address and all flags.
+	fn clear(&mut self) {
+		self.physical_address_and_flags = 0;
+	}
+
 	/// Mark this as a valid (present) entry and set address translation and flags.
+	/// Pages larger than 4 KiB have to be aligned to their size, which is verified by map_page_in_this_table.
 	///
 	/// # Arguments
 	///
 	/// * `physical_address` - The physical memory address this entry shall translate to
 	/// * `flags` - Flags from PageTableEntryFlags (note that the PRESENT and ACCESSED flags are set automatically)
 	fn set(&mut self, physical_address: usize, flags: PageTableEntryFlags) {
-		if flags.contains(PageTableEntryFlags::HUGE_PAGE) {
-			// HUGE_PAGE may indicate a 2 MiB or 1 GiB page.
-			// We don't know this here, so we can only verify that at least the offset bits for a 2 MiB page are zero.
-			assert_eq!(
-				physical_address % LargePageSize::SIZE,
-				0,
-				"Physical address is not on a 2 MiB page boundary (physical_address = {:#x})",
-				physical_address
-			);
-		} else {
-			// Verify that the offset bits for a 4 KiB page are zero.
-			assert_eq!(
-				physical_address % BasePageSize::SIZE,
-				0,
-				"Physical address is not on a 4 KiB page boundary (physical_address = {:#x})",
-				physical_address
-			);
-		}
+		// Verify that the offset bits for a 4 KiB page are zero.
+		assert_eq!(
+			physical_address % BasePageSize::SIZE,
+			0,
+			"Physical address is not on a 4 KiB page boundary (physical_address = {:#x})",
+			physical_address
+		);
 
 		self.physical_address_and_flags = physical_address
 			| (PageTableEntryFlags::PRESENT | PageTableEntryFlags::ACCESSED | flags).bits();
 	}
 }
 
+impl fmt::Debug for PageTableEntry {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("PageTableEntry")
+			.field("address", &format_args!("{:#x}", self.address()))
+			.field("flags", &self.flags())
+			.finish()
+	}
+}
+
 /// A generic interface to support all possible page sizes.
 ///
 /// This is defined as a subtrait of Copy to enable #[derive(Clone, Copy)] for Page.
@@ -139,6 +318,17 @@ impl PageSize for LargePageSize {
 	const MAP_EXTRA_FLAG: PageTableEntryFlags = PageTableEntryFlags::HUGE_PAGE;
 }
 
+/// A 1 GiB page mapped in the PDPT.
+///
+/// Not all CPUs support 1 GiB pages (CPUID.80000001H:EDX.Page1GB[bit 26]).
+#[derive(Clone, Copy)]
+pub enum HugePageSize {}
+impl PageSize for HugePageSize {
+	const SIZE: usize = 1024 * 1024 * 1024;
+	const MAP_LEVEL: usize = 2;
+	const MAP_EXTRA_FLAG: PageTableEntryFlags = PageTableEntryFlags::HUGE_PAGE;
+}
+
 /// A memory page of the size given by S.
 #[derive(Clone, Copy)]
 struct Page<S: PageSize> {
@@ -153,22 +343,21 @@ struct Page<S: PageSize> {
 impl<S: PageSize> Page<S> {
 	/// Flushes this page from the TLB of this CPU.
 	fn flush_from_tlb(&self) {
-		unsafe {
-			asm!("invlpg [{}]", in(reg) self.virtual_address, options(nostack, preserves_flags));
-		}
+		ActiveTables::flush_page(self.virtual_address);
 	}
 
 	/// Returns whether the given virtual address is a valid one in the x86-64 memory model.
 	///
-	/// Current x86-64 supports only 48-bit for virtual memory addresses.
+	/// With 4-level paging, x86-64 supports only 48-bit for virtual memory addresses.
 	/// This is enforced by requiring bits 63 through 48 to replicate bit 47 (cf. Intel Vol. 1, 3.3.7.1).
 	/// As a consequence, the address space is divided into the two valid regions 0x8000_0000_0000
 	/// and 0xFFFF_8000_0000_0000.
+	/// With 5-level paging (LA57), bits 63 through 57 have to replicate bit 56 instead.
 	///
-	/// Although we could make this check depend on the actual linear address width from the CPU,
-	/// any extension above 48-bit would require a new page table level, which we don't implement.
+	/// CPUs may implement fewer bits, see [`init_linear_address_width`].
 	fn is_valid_address(virtual_address: usize) -> bool {
-		!(0x8000_0000_0000..0xFFFF_8000_0000_0000).contains(&virtual_address)
+		let upper_bits = (virtual_address as isize) >> SIGN_BIT.load(Ordering::Relaxed);
+		upper_bits == 0 || upper_bits == -1
 	}
 
 	/// Returns a Page including the given virtual address.
@@ -194,6 +383,7 @@ impl<S: PageSize> Page<S> {
 	/// Returns the index of this page in the table given by L.
 	fn table_index<L: PageTableLevel>(&self) -> usize {
 		assert!(L::LEVEL >= S::MAP_LEVEL);
+		debug_assert!(L::LEVEL < PAGE_LEVELS);
 		self.virtual_address >> PAGE_BITS >> (L::LEVEL * PAGE_MAP_BITS) & PAGE_MAP_MASK
 	}
 }
@@ -218,10 +408,81 @@ impl<S: PageSize> Iterator for PageIter<S> {
 	}
 }
 
-/// An interface to allow for a generic implementation of struct PageTable for all 4 page tables.
+/// A range of pages of size S, which yields the virtual address of each page.
+#[derive(Clone, Copy, Debug)]
+pub struct PageRange<S: PageSize> {
+	/// Virtual address of the next page.
+	start: usize,
+
+	/// Virtual address after the last page.
+	end: usize,
+
+	/// Required by Rust to support the S parameter.
+	size: PhantomData<S>,
+}
+
+impl<S: PageSize> PageRange<S> {
+	/// Returns the range of pages covering the virtual addresses from `start` up to (excluding) `end`.
+	///
+	/// `start` is rounded down and `end` is rounded up to a page size boundary.
+	pub fn new(start: usize, end: usize) -> Self {
+		assert!(
+			start <= end,
+			"Page range [{:#x} - {:#x}] ends before it starts",
+			start,
+			end
+		);
+		assert!(
+			is_canonical_range(start..end),
+			"Page range [{:#x} - {:#x}] is not canonical",
+			start,
+			end
+		);
+
+		Self {
+			start: align_down!(start, S::SIZE),
+			end: align_up!(end, S::SIZE),
+			size: PhantomData,
+		}
+	}
+
+	/// Returns the range of `count` pages starting with the page including `start`.
+	#[allow(dead_code)]
+	pub fn with_count(start: usize, count: usize) -> Self {
+		let start = align_down!(start, S::SIZE);
+		Self::new(start, start + count * S::SIZE)
+	}
+
+	/// Returns the virtual address of the first page.
+	pub fn start(&self) -> usize {
+		self.start
+	}
+
+	/// Returns the number of pages in this range.
+	#[allow(dead_code)]
+	pub fn count(&self) -> usize {
+		(self.end - self.start) / S::SIZE
+	}
+}
+
+impl<S: PageSize> Iterator for PageRange<S> {
+	type Item = usize;
+
+	fn next(&mut self) -> Option<usize> {
+		if self.start < self.end {
+			let virtual_address = self.start;
+			self.start += S::SIZE;
+			Some(virtual_address)
+		} else {
+			None
+		}
+	}
+}
+
+/// An interface to allow for a generic implementation of struct PageTable for all page tables.
 /// Must be implemented by all page tables.
 trait PageTableLevel {
-	/// Numeric page table level (from 0 for PGT through 3 for PML4) to enable numeric comparisons.
+	/// Numeric page table level (from 0 for PGT through 3 for PML4, or 4 for PML5) to enable numeric comparisons.
 	const LEVEL: usize;
 }
 
@@ -234,6 +495,19 @@ trait PageTableLevelWithSubtables: PageTableLevel {
 	type SubtableLevel;
 }
 
+/// The Page Map Level 5 (PML5) table, with numeric level 4 and PML4 subtables.
+#[cfg(feature = "la57")]
+enum PML5 {}
+#[cfg(feature = "la57")]
+impl PageTableLevel for PML5 {
+	const LEVEL: usize = 4;
+}
+
+#[cfg(feature = "la57")]
+impl PageTableLevelWithSubtables for PML5 {
+	type SubtableLevel = PML4;
+}
+
 /// The Page Map Level 4 (PML4) table, with numeric level 3 and PDPT subtables.
 enum PML4 {}
 impl PageTableLevel for PML4 {
@@ -270,7 +544,23 @@ impl PageTableLevel for PGT {
 	const LEVEL: usize = 0;
 }
 
-/// Representation of any page table (PML4, PDPT, PDT, PGT) in memory.
+// The type-level recursion in map_page relies on these levels forming a gapless chain from the root down to 0.
+// Fail the build if any of them is changed without adjusting PAGE_LEVELS.
+#[allow(clippy::assertions_on_constants)]
+const _: () = {
+	assert!(RootLevel::LEVEL == PAGE_LEVELS - 1);
+	#[cfg(feature = "la57")]
+	assert!(PML4::LEVEL == PML5::LEVEL - 1);
+	assert!(PDPT::LEVEL == PML4::LEVEL - 1);
+	assert!(PDT::LEVEL == PDPT::LEVEL - 1);
+	assert!(PGT::LEVEL == PDT::LEVEL - 1);
+	assert!(PGT::LEVEL == 0);
+	assert!(BasePageSize::MAP_LEVEL < PAGE_LEVELS);
+	assert!(LargePageSize::MAP_LEVEL < PAGE_LEVELS);
+	assert!(HugePageSize::MAP_LEVEL < PAGE_LEVELS);
+};
+
+/// Representation of any page table (PML5, PML4, PDPT, PDT, PGT) in memory.
 /// Parameter L supplies information for Rust's typing system to distinguish between the different tables.
 struct PageTable<L> {
 	/// Each page table has 512 entries (can be calculated using PAGE_MAP_BITS).
@@ -280,27 +570,129 @@ struct PageTable<L> {
 	level: PhantomData<L>,
 }
 
+impl<L: PageTableLevel> PageTable<L> {
+	/// Size of the memory region covered by a single entry of this table.
+	const ENTRY_SIZE: usize = 1 << (PAGE_BITS + L::LEVEL * PAGE_MAP_BITS);
+
+	/// Returns whether no entry of this table is present or a guard page.
+	fn is_empty(&self) -> bool {
+		self.entries
+			.iter()
+			.all(|entry| !entry.is_present() && !entry.is_guard())
+	}
+
+	/// Marks all entries of this table as unused.
+	fn zero(&mut self) {
+		for entry in self.entries.iter_mut() {
+			entry.clear();
+		}
+	}
+}
+
 /// A trait defining methods every page table has to implement.
 /// This additional trait is necessary to make use of Rust's specialization feature and provide a default
 /// implementation of some methods.
 trait PageTableMethods {
+	fn get_page_table_entry<S: PageSize>(&mut self, page: Page<S>) -> Option<PageTableEntry>;
+	fn get_page_table_entry_mut<S: PageSize>(
+		&mut self,
+		page: Page<S>,
+	) -> Option<&mut PageTableEntry>;
+	fn unmap_below(&mut self, table_address: usize, end_address: usize) -> bool;
+	fn page_table_frames_needed<S: PageSize>(&mut self, page: Page<S>, first: bool) -> usize;
 	fn map_page_in_this_table<S: PageSize>(
 		&mut self,
 		page: Page<S>,
 		physical_address: usize,
 		flags: PageTableEntryFlags,
 	) -> bool;
-	fn map_page<S: PageSize>(
+	fn map_page<S: PageSize, A: PageTableAllocator>(
 		&mut self,
 		page: Page<S>,
 		physical_address: usize,
 		flags: PageTableEntryFlags,
-	) -> bool;
+		allocator: &mut A,
+	) -> Result<bool, MapError>;
+	fn unmap_page_in_this_table<S: PageSize>(&mut self, page: Page<S>);
+	fn unmap_page<S: PageSize>(&mut self, page: Page<S>);
+	fn is_guard_page<S: PageSize>(&mut self, page: Page<S>) -> bool;
+	fn map_guard_page<A: PageTableAllocator>(
+		&mut self,
+		page: Page<BasePageSize>,
+		allocator: &mut A,
+	) -> Result<(), MapError>;
 }
 
 impl<L: PageTableLevel> PageTableMethods for PageTable<L> {
+	/// Returns the PageTableEntry for the given page if it is present, otherwise returns None.
+	///
+	/// This is the default implementation called only for PGT.
+	/// It is overridden by a specialized implementation for all tables with sub tables (all except PGT).
+	default fn get_page_table_entry<S: PageSize>(
+		&mut self,
+		page: Page<S>,
+	) -> Option<PageTableEntry> {
+		assert_eq!(L::LEVEL, S::MAP_LEVEL);
+		let index = page.table_index::<L>();
+
+		if self.entries[index].is_present() {
+			Some(self.entries[index])
+		} else {
+			None
+		}
+	}
+
+	/// Returns a mutable reference to the PageTableEntry for the given page if it is present, otherwise returns None.
+	///
+	/// This is the default implementation called only for PGT.
+	/// It is overridden by a specialized implementation for all tables with sub tables (all except PGT).
+	default fn get_page_table_entry_mut<S: PageSize>(
+		&mut self,
+		page: Page<S>,
+	) -> Option<&mut PageTableEntry> {
+		assert_eq!(L::LEVEL, S::MAP_LEVEL);
+		let index = page.table_index::<L>();
+
+		if self.entries[index].is_present() {
+			Some(&mut self.entries[index])
+		} else {
+			None
+		}
+	}
+
+	/// Unmaps all pages in this table that lie completely below `end_address`.
+	/// `table_address` is the virtual address of the first page in this table.
+	/// Returns whether this table is empty afterwards.
+	///
+	/// This is the default implementation called only for PGT.
+	/// It is overridden by a specialized implementation for all tables with sub tables (all except PGT).
+	default fn unmap_below(&mut self, table_address: usize, end_address: usize) -> bool {
+		for (index, entry) in self.entries.iter_mut().enumerate() {
+			let virtual_address = table_address + index * Self::ENTRY_SIZE;
+			if virtual_address + Self::ENTRY_SIZE > end_address {
+				break;
+			}
+
+			entry.clear();
+		}
+
+		self.is_empty()
+	}
+
+	/// Returns the number of page tables that have to be allocated for mapping the given page.
+	///
+	/// This is the default implementation called only for PGT, which never needs any further tables.
+	/// It is overridden by a specialized implementation for all tables with sub tables (all except PGT).
+	default fn page_table_frames_needed<S: PageSize>(
+		&mut self,
+		_page: Page<S>,
+		_first: bool,
+	) -> usize {
+		0
+	}
+
 	/// Maps a single page in this table to the given physical address.
-	/// Returns whether an existing entry was updated. You can use this return value to flush TLBs.
+	/// Returns whether an existing entry was updated, in which case the caller has to flush the page from the TLB.
 	///
 	/// Must only be called if a page of this size is mapped at this page table level!
 	fn map_page_in_this_table<S: PageSize>(
@@ -310,6 +702,13 @@ impl<L: PageTableLevel> PageTableMethods for PageTable<L> {
 		flags: PageTableEntryFlags,
 	) -> bool {
 		assert_eq!(L::LEVEL, S::MAP_LEVEL);
+		assert_eq!(
+			physical_address % S::SIZE,
+			0,
+			"Physical address is not on a {} KiB page boundary (physical_address = {:#x})",
+			S::SIZE / 1024,
+			physical_address
+		);
 		let index = page.table_index::<L>();
 		let flush = self.entries[index].is_present();
 
@@ -318,25 +717,78 @@ impl<L: PageTableLevel> PageTableMethods for PageTable<L> {
 			PageTableEntryFlags::DIRTY | S::MAP_EXTRA_FLAG | flags,
 		);
 
-		if flush {
-			page.flush_from_tlb();
-		}
-
 		flush
 	}
 
 	/// Maps a single page to the given physical address.
-	/// Returns whether an existing entry was updated. You can use this return value to flush TLBs.
+	/// Returns whether an existing entry was updated, in which case the caller has to flush the page from the TLB.
 	///
 	/// This is the default implementation that just calls the map_page_in_this_table method.
 	/// It is overridden by a specialized implementation for all tables with sub tables (all except PGT).
-	default fn map_page<S: PageSize>(
+	default fn map_page<S: PageSize, A: PageTableAllocator>(
 		&mut self,
 		page: Page<S>,
 		physical_address: usize,
 		flags: PageTableEntryFlags,
-	) -> bool {
-		self.map_page_in_this_table::<S>(page, physical_address, flags)
+		_allocator: &mut A,
+	) -> Result<bool, MapError> {
+		if self.entries[page.table_index::<L>()].is_guard() {
+			return Err(MapError::AlreadyMapped);
+		}
+
+		Ok(self.map_page_in_this_table::<S>(page, physical_address, flags))
+	}
+
+	/// Unmaps a single page in this table and flushes it from the TLB.
+	///
+	/// Must only be called if a page of this size is mapped at this page table level!
+	fn unmap_page_in_this_table<S: PageSize>(&mut self, page: Page<S>) {
+		assert_eq!(L::LEVEL, S::MAP_LEVEL);
+		let index = page.table_index::<L>();
+		assert!(
+			self.entries[index].is_present(),
+			"Trying to unmap page {:#x}, which is not mapped",
+			page.virtual_address
+		);
+
+		self.entries[index].clear();
+		page.flush_from_tlb();
+	}
+
+	/// Unmaps a single page and flushes it from the TLB.
+	///
+	/// This is the default implementation that just calls the unmap_page_in_this_table method.
+	/// It is overridden by a specialized implementation for all tables with sub tables (all except PGT).
+	default fn unmap_page<S: PageSize>(&mut self, page: Page<S>) {
+		self.unmap_page_in_this_table::<S>(page)
+	}
+
+	/// Returns whether the given page is a guard page.
+	///
+	/// This is the default implementation called only for PGT.
+	/// It is overridden by a specialized implementation for all tables with sub tables (all except PGT).
+	default fn is_guard_page<S: PageSize>(&mut self, page: Page<S>) -> bool {
+		assert_eq!(L::LEVEL, S::MAP_LEVEL);
+		self.entries[page.table_index::<L>()].is_guard()
+	}
+
+	/// Installs a guard page, which is reserved but not present.
+	///
+	/// This is the default implementation called only for PGT.
+	/// It is overridden by a specialized implementation for all tables with sub tables (all except PGT).
+	default fn map_guard_page<A: PageTableAllocator>(
+		&mut self,
+		page: Page<BasePageSize>,
+		_allocator: &mut A,
+	) -> Result<(), MapError> {
+		assert_eq!(L::LEVEL, BasePageSize::MAP_LEVEL);
+		let entry = &mut self.entries[page.table_index::<L>()];
+		if entry.is_present() || entry.is_guard() {
+			return Err(MapError::AlreadyMapped);
+		}
+
+		entry.physical_address_and_flags = PageTableEntryFlags::GUARD.bits();
+		Ok(())
 	}
 }
 
@@ -344,90 +796,1118 @@ impl<L: PageTableLevelWithSubtables> PageTableMethods for PageTable<L>
 where
 	L::SubtableLevel: PageTableLevel,
 {
-	/// Maps a single page to the given physical address.
-	/// Returns whether an existing entry was updated. You can use this return value to flush TLBs.
+	/// Returns the PageTableEntry for the given page if it is present, otherwise returns None.
 	///
-	/// This is the implementation for all tables with subtables (PML4, PDPT, PDT).
-	/// It overrides the default implementation above.
-	fn map_page<S: PageSize>(
+	/// This is the implementation for all tables with subtables (PML5, PML4, PDPT, PDT).
+	/// If the page is part of a larger page, the entry of the larger page is returned.
+	fn get_page_table_entry<S: PageSize>(&mut self, page: Page<S>) -> Option<PageTableEntry> {
+		assert!(L::LEVEL >= S::MAP_LEVEL);
+		let index = page.table_index::<L>();
+
+		if !self.entries[index].is_present() {
+			None
+		} else if L::LEVEL > S::MAP_LEVEL && !self.entries[index].is_huge() {
+			let subtable = self.subtable::<S>(page);
+			subtable.get_page_table_entry::<S>(page)
+		} else {
+			Some(self.entries[index])
+		}
+	}
+
+	/// Returns a mutable reference to the PageTableEntry for the given page if it is present, otherwise returns None.
+	///
+	/// This is the implementation for all tables with subtables (PML5, PML4, PDPT, PDT).
+	/// Unlike get_page_table_entry, this only returns entries of pages of size S.
+	fn get_page_table_entry_mut<S: PageSize>(
 		&mut self,
 		page: Page<S>,
-		physical_address: usize,
-		flags: PageTableEntryFlags,
-	) -> bool {
+	) -> Option<&mut PageTableEntry> {
 		assert!(L::LEVEL >= S::MAP_LEVEL);
+		let index = page.table_index::<L>();
 
-		if L::LEVEL > S::MAP_LEVEL {
-			let index = page.table_index::<L>();
-
-			// Does the table exist yet?
-			if !self.entries[index].is_present() {
-				// Allocate a single 4 KiB page for the new entry and mark it as a valid, writable subtable.
-				let physical_address = physicalmem::allocate(BasePageSize::SIZE);
-				self.entries[index].set(physical_address, PageTableEntryFlags::WRITABLE);
-
-				// Mark all entries as unused in the newly created table.
-				let subtable = self.subtable::<S>(page);
-				for entry in subtable.entries.iter_mut() {
-					entry.physical_address_and_flags = 0;
-				}
+		if !self.entries[index].is_present() {
+			None
+		} else if L::LEVEL > S::MAP_LEVEL {
+			if self.entries[index].is_huge() {
+				return None;
 			}
 
 			let subtable = self.subtable::<S>(page);
-			subtable.map_page::<S>(page, physical_address, flags)
+			subtable.get_page_table_entry_mut::<S>(page)
 		} else {
-			// Calling the default implementation from a specialized one is not supported (yet),
-			// so we have to resort to an extra function.
-			self.map_page_in_this_table::<S>(page, physical_address, flags)
+			Some(&mut self.entries[index])
 		}
 	}
-}
 
-impl<L: PageTableLevelWithSubtables> PageTable<L>
-where
-	L::SubtableLevel: PageTableLevel,
-{
-	/// Returns the next subtable for the given page in the page table hierarchy.
+	/// Unmaps all pages in this table that lie completely below `end_address`.
+	/// `table_address` is the virtual address of the first page in this table.
+	/// Returns whether this table is empty afterwards.
 	///
-	/// Must only be called if a page of this size is mapped in a subtable!
-	fn subtable<S: PageSize>(&mut self, page: Page<S>) -> &mut PageTable<L::SubtableLevel> {
-		assert!(L::LEVEL > S::MAP_LEVEL);
+	/// This is the implementation for all tables with subtables (PML5, PML4, PDPT, PDT).
+	/// Subtables that become empty are freed.
+	fn unmap_below(&mut self, table_address: usize, end_address: usize) -> bool {
+		for index in 0..self.entries.len() {
+			let virtual_address = table_address + index * Self::ENTRY_SIZE;
+			if virtual_address >= end_address {
+				break;
+			}
 
-		// Calculate the address of the subtable.
-		let index = page.table_index::<L>();
-		let table_address = self as *const PageTable<L> as usize;
-		let subtable_address = (table_address << PAGE_MAP_BITS) | (index << PAGE_BITS);
-		unsafe { &mut *(subtable_address as *mut PageTable<L::SubtableLevel>) }
+			if !self.entries[index].is_present() {
+				continue;
+			}
+
+			if self.entries[index].is_huge() {
+				if virtual_address + Self::ENTRY_SIZE <= end_address {
+					self.entries[index].clear();
+				}
+			} else if self
+				.subtable_at(index)
+				.unmap_below(virtual_address, end_address)
+			{
+				self.free_subtable(index);
+			}
+		}
+
+		self.is_empty()
 	}
 
-	/// Maps a continuous range of pages.
+	/// Returns the number of page tables that have to be allocated for mapping the given page.
 	///
-	/// # Arguments
+	/// When walking through a range of pages, a missing table is only counted for the first page it covers.
+	/// `first` marks the first page of the range, which counts all tables that are missing.
 	///
-	/// * `range` - The range of pages of size S
-	/// * `physical_address` - First physical address to map these pages to
-	/// * `flags` - Flags from PageTableEntryFlags to set for the page table entry (e.g. WRITABLE or EXECUTE_DISABLE).
-	///             The PRESENT, ACCESSED, and DIRTY flags are already set automatically.
-	fn map_pages<S: PageSize>(
-		&mut self,
-		range: PageIter<S>,
-		physical_address: usize,
-		flags: PageTableEntryFlags,
-	) {
-		let mut current_physical_address = physical_address;
+	/// This is the implementation for all tables with subtables (PML5, PML4, PDPT, PDT).
+	fn page_table_frames_needed<S: PageSize>(&mut self, page: Page<S>, first: bool) -> usize {
+		assert!(L::LEVEL >= S::MAP_LEVEL);
+
+		if L::LEVEL == S::MAP_LEVEL {
+			return 0;
+		}
 
-		for page in range {
-			self.map_page::<S>(page, current_physical_address, flags);
-			current_physical_address += S::SIZE;
+		let index = page.table_index::<L>();
+		if !self.entries[index].is_present() {
+			// All tables from the missing subtable down to the level of the page have to be allocated.
+			// A table at level `level` covers a single entry of the table above it.
+			(S::MAP_LEVEL..L::LEVEL)
+				.filter(|level| {
+					let table_size = 1 << (PAGE_BITS + (level + 1) * PAGE_MAP_BITS);
+					first || page.virtual_address % table_size == 0
+				})
+				.count()
+		} else if self.entries[index].is_huge() {
+			0
+		} else {
+			self.subtable::<S>(page)
+				.page_table_frames_needed::<S>(page, first)
 		}
 	}
-}
 
-#[inline]
-fn get_page_range<S: PageSize>(virtual_address: usize, count: usize) -> PageIter<S> {
-	let first_page = Page::<S>::including_address(virtual_address);
-	let last_page = Page::<S>::including_address(virtual_address + (count - 1) * S::SIZE);
-	Page::range(first_page, last_page)
+	/// Maps a single page to the given physical address.
+	/// Returns whether an existing entry was updated. You can use this return value to flush TLBs.
+	///
+	/// This is the implementation for all tables with subtables (PML5, PML4, PDPT, PDT).
+	/// It overrides the default implementation above.
+	fn map_page<S: PageSize, A: PageTableAllocator>(
+		&mut self,
+		page: Page<S>,
+		physical_address: usize,
+		flags: PageTableEntryFlags,
+		allocator: &mut A,
+	) -> Result<bool, MapError> {
+		assert!(L::LEVEL >= S::MAP_LEVEL);
+
+		if L::LEVEL > S::MAP_LEVEL {
+			let subtable = self.subtable_or_create::<S, A>(page, allocator)?;
+			subtable.map_page::<S, A>(page, physical_address, flags, allocator)
+		} else {
+			if self.entries[page.table_index::<L>()].is_guard() {
+				return Err(MapError::AlreadyMapped);
+			}
+
+			// Calling the default implementation from a specialized one is not supported (yet),
+			// so we have to resort to an extra function.
+			Ok(self.map_page_in_this_table::<S>(page, physical_address, flags))
+		}
+	}
+
+	/// Returns whether the given page is a guard page.
+	///
+	/// This is the implementation for all tables with subtables (PML5, PML4, PDPT, PDT).
+	/// It overrides the default implementation above.
+	fn is_guard_page<S: PageSize>(&mut self, page: Page<S>) -> bool {
+		assert!(L::LEVEL >= S::MAP_LEVEL);
+		let index = page.table_index::<L>();
+
+		if L::LEVEL == S::MAP_LEVEL {
+			self.entries[index].is_guard()
+		} else if self.entries[index].is_present() && !self.entries[index].is_huge() {
+			self.subtable::<S>(page).is_guard_page::<S>(page)
+		} else {
+			false
+		}
+	}
+
+	/// Installs a guard page, which is reserved but not present, creating missing subtables.
+	///
+	/// This is the implementation for all tables with subtables (PML5, PML4, PDPT, PDT).
+	/// It overrides the default implementation above.
+	fn map_guard_page<A: PageTableAllocator>(
+		&mut self,
+		page: Page<BasePageSize>,
+		allocator: &mut A,
+	) -> Result<(), MapError> {
+		if self.entries[page.table_index::<L>()].is_huge() {
+			return Err(MapError::AlreadyMapped);
+		}
+
+		let subtable = self.subtable_or_create::<BasePageSize, A>(page, allocator)?;
+		subtable.map_guard_page::<A>(page, allocator)
+	}
+
+	/// Unmaps a single page and flushes it from the TLB.
+	/// Subtables that become empty are returned to the physical memory manager.
+	///
+	/// This is the implementation for all tables with subtables (PML5, PML4, PDPT, PDT).
+	/// It overrides the default implementation above.
+	fn unmap_page<S: PageSize>(&mut self, page: Page<S>) {
+		assert!(L::LEVEL >= S::MAP_LEVEL);
+
+		if L::LEVEL > S::MAP_LEVEL {
+			let index = page.table_index::<L>();
+			assert!(
+				self.entries[index].is_present() && !self.entries[index].is_huge(),
+				"Trying to unmap page {:#x}, which is not mapped",
+				page.virtual_address
+			);
+
+			let subtable = self.subtable::<S>(page);
+			subtable.unmap_page::<S>(page);
+
+			if subtable.is_empty() {
+				self.free_subtable(index);
+			}
+		} else {
+			self.unmap_page_in_this_table::<S>(page)
+		}
+	}
+}
+
+impl<L: PageTableLevelWithSubtables> PageTable<L>
+where
+	L::SubtableLevel: PageTableLevel,
+{
+	/// Returns the next subtable for the given page in the page table hierarchy.
+	///
+	/// Must only be called if a page of this size is mapped in a subtable!
+	fn subtable<S: PageSize>(&mut self, page: Page<S>) -> &mut PageTable<L::SubtableLevel> {
+		assert!(L::LEVEL > S::MAP_LEVEL);
+		self.subtable_at(page.table_index::<L>())
+	}
+
+	/// Returns the next subtable for the given page in the page table hierarchy, creating it if it does not exist.
+	///
+	/// Must not be called if the entry references a huge page!
+	fn subtable_or_create<S: PageSize, A: PageTableAllocator>(
+		&mut self,
+		page: Page<S>,
+		allocator: &mut A,
+	) -> Result<&mut PageTable<L::SubtableLevel>, MapError> {
+		let index = page.table_index::<L>();
+
+		// Does the table exist yet?
+		if !self.entries[index].is_present() {
+			// Allocate a single 4 KiB page for the new entry and mark it as a valid, writable subtable.
+			let physical_address = allocator.allocate_frame().ok_or(MapError::OutOfMemory)?;
+			self.entries[index].set(physical_address, PageTableEntryFlags::WRITABLE);
+
+			// Mark all entries as unused in the newly created table.
+			let subtable = self.subtable::<S>(page);
+			subtable.zero();
+			debug_assert!(subtable.is_empty());
+		}
+
+		Ok(self.subtable::<S>(page))
+	}
+
+	/// Clears the entry at the given index and returns the subtable it references to the physical memory manager.
+	///
+	/// Subtables that have not been allocated from the physical memory manager, such as the static boot page
+	/// tables in the loader image, stay in place.
+	///
+	/// Must only be called if the subtable is empty!
+	fn free_subtable(&mut self, index: usize) {
+		// The recursive entry refers to the root page table itself, which must never be freed.
+		assert!(
+			L::LEVEL != RootLevel::LEVEL || index != RECURSIVE_INDEX,
+			"Trying to free the root page table"
+		);
+		debug_assert!(self.subtable_at(index).is_empty());
+
+		if !physicalmem::is_managed(self.entries[index].address()) {
+			return;
+		}
+
+		let subtable_address = self.subtable_at(index) as *const _ as usize;
+		physicalmem::deallocate(self.entries[index].address(), BasePageSize::SIZE);
+		self.entries[index].clear();
+
+		// The subtable may still be cached in the TLB through the recursive mapping.
+		ActiveTables::flush_page(subtable_address);
+	}
+
+	/// Returns the subtable referenced by the entry at the given index.
+	///
+	/// Must only be called if this entry is present and does not reference a huge page!
+	fn subtable_at(&mut self, index: usize) -> &mut PageTable<L::SubtableLevel> {
+		debug_assert_eq!(L::SubtableLevel::LEVEL + 1, L::LEVEL);
+
+		// Calculate the address of the subtable.
+		let table_address = self as *const PageTable<L> as usize;
+		let subtable_address =
+			ActiveTables::subtable_address(table_address, index, self.entries[index]);
+		unsafe { &mut *(subtable_address as *mut PageTable<L::SubtableLevel>) }
+	}
+
+	/// Maps a continuous range of pages.
+	///
+	/// # Arguments
+	///
+	/// * `range` - The range of pages of size S
+	/// * `frames` - Physical addresses of the frames to map these pages to, one for each page
+	/// * `flags` - Flags from PageTableEntryFlags to set for the page table entry (e.g. WRITABLE or EXECUTE_DISABLE).
+	///             The PRESENT, ACCESSED, and DIRTY flags are already set automatically.
+	/// * `allocator` - Allocator for the frames of missing page tables
+	///
+	/// Overwritten entries are flushed from the TLB after mapping the whole range.
+	/// Up to [`FLUSH_ALL_THRESHOLD`] pages are flushed individually, beyond that the whole TLB is flushed at once.
+	fn map_pages<S: PageSize, A: PageTableAllocator>(
+		&mut self,
+		range: PageIter<S>,
+		frames: impl Iterator<Item = usize>,
+		flags: PageTableEntryFlags,
+		allocator: &mut A,
+	) -> Result<(), MapError> {
+		let mut flush_pages = [Page::<S>::including_address(0); FLUSH_ALL_THRESHOLD];
+		let mut flush_count = 0;
+
+		let mut result = Ok(());
+
+		for (page, frame) in range.zip(frames) {
+			match self.map_page::<S, A>(page, frame, flags, allocator) {
+				Ok(false) => {}
+				Ok(true) => {
+					if let Some(flush_page) = flush_pages.get_mut(flush_count) {
+						*flush_page = page;
+					}
+					flush_count += 1;
+				}
+				Err(err) => {
+					result = Err(err);
+					break;
+				}
+			}
+		}
+
+		// Also flush if mapping failed halfway, since the pages mapped so far stay mapped.
+		if flush_count > FLUSH_ALL_THRESHOLD {
+			if flags.contains(PageTableEntryFlags::GLOBAL) {
+				flush_tlb_global();
+			} else {
+				flush_tlb_all();
+			}
+		} else {
+			for page in &flush_pages[..flush_count] {
+				page.flush_from_tlb();
+			}
+		}
+
+		result
+	}
+}
+
+/// Access to the page tables in memory.
+///
+/// The loader operates on the active page tables through the recursive mapping.
+/// Tests substitute page tables in host memory instead, which are accessed through their physical addresses.
+trait PageTableMemory {
+	/// Returns the root page table (PML4, or PML5 with 5-level paging).
+	fn root() -> &'static mut PageTable<RootLevel>;
+
+	/// Returns the virtual address of the subtable referenced by `entry`, which is the entry at `index` of the
+	/// table at the virtual address `table_address`.
+	fn subtable_address(table_address: usize, index: usize, entry: PageTableEntry) -> usize;
+
+	/// Flushes the page containing the given virtual address from the TLB of this CPU.
+	fn flush_page(virtual_address: usize);
+}
+
+/// The active page tables, which are accessed through the recursive mapping.
+#[cfg(not(test))]
+enum RecursiveTables {}
+
+#[cfg(not(test))]
+impl PageTableMemory for RecursiveTables {
+	fn root() -> &'static mut PageTable<RootLevel> {
+		unsafe { &mut *ROOT_PAGETABLE_ADDRESS }
+	}
+
+	fn subtable_address(table_address: usize, index: usize, _entry: PageTableEntry) -> usize {
+		recursive_subtable_address(table_address, index)
+	}
+
+	fn flush_page(virtual_address: usize) {
+		unsafe {
+			asm!("invlpg [{}]", in(reg) virtual_address, options(nostack, preserves_flags));
+		}
+	}
+}
+
+/// The page tables that are operated on.
+#[cfg(not(test))]
+type ActiveTables = RecursiveTables;
+
+/// The page tables that are operated on.
+#[cfg(test)]
+type ActiveTables = tests::HostTables;
+
+/// Returns the virtual address through which the recursive mapping exposes the subtable referenced by the entry
+/// at `index` of the table at the virtual address `table_address`.
+///
+/// Shifting the table address by one level drops the recursive index at the top and makes room for `index`
+/// at the bottom.
+#[inline]
+fn recursive_subtable_address(table_address: usize, index: usize) -> usize {
+	(table_address << PAGE_MAP_BITS) | (index << PAGE_BITS)
+}
+
+/// Number of overwritten pages up to which [`PageTableMethods::map_pages`] flushes each page from the TLB individually.
+///
+/// Beyond this, reloading CR3 once is cheaper than invalidating every single page.
+const FLUSH_ALL_THRESHOLD: usize = 32;
+
+#[inline]
+fn get_page_range<S: PageSize>(virtual_address: usize, count: usize) -> PageIter<S> {
+	let first_page = Page::<S>::including_address(virtual_address);
+	let last_page = Page::<S>::including_address(virtual_address + (count - 1) * S::SIZE);
+	Page::range(first_page, last_page)
+}
+
+/// Returns the physical address of the active root page table (PML4, or PML5 with 5-level paging).
+///
+/// This is read from CR3 with all flag bits masked off.
+pub fn current_pml4_phys() -> usize {
+	let cr3: usize;
+	unsafe {
+		asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+	}
+	cr3 & PHYSICAL_ADDRESS_MASK
+}
+
+/// Flushes all non-global pages from the TLB of this CPU by reloading CR3.
+///
+/// After changing many mappings at once, this is cheaper than flushing each page.
+pub fn flush_tlb_all() {
+	unsafe {
+		asm!(
+			"mov {0}, cr3",
+			"mov cr3, {0}",
+			out(reg) _,
+			options(nostack, preserves_flags),
+		);
+	}
+}
+
+/// Flushes all pages including global ones from the TLB of this CPU by toggling CR4.PGE.
+///
+/// Without global pages enabled, this is the same as [`flush_tlb_all`].
+pub fn flush_tlb_global() {
+	/// Page Global Enable (CR4.PGE)
+	const CR4_PGE: usize = 1 << 7;
+
+	let cr4: usize;
+	unsafe {
+		asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+	}
+
+	if cr4 & CR4_PGE == 0 {
+		flush_tlb_all();
+		return;
+	}
+
+	unsafe {
+		asm!(
+			"mov cr4, {}",
+			"mov cr4, {}",
+			in(reg) cr4 & !CR4_PGE,
+			in(reg) cr4,
+			options(nostack, preserves_flags),
+		);
+	}
+}
+
+/// An address space with its own root page table, which may be different from the active one.
+///
+/// The root page table of an address space must be identity-mapped.
+pub struct AddressSpace {
+	/// Physical address of the root page table of this address space.
+	root_physical_address: usize,
+}
+
+/// Creates a new, empty address space.
+///
+/// The new root page table is identity-mapped in the active address space and only contains its recursive entry.
+#[allow(dead_code)]
+pub fn new_address_space() -> Result<AddressSpace, MapError> {
+	let root_physical_address =
+		physicalmem::try_allocate(BasePageSize::SIZE).ok_or(MapError::OutOfMemory)?;
+	map::<BasePageSize>(
+		root_physical_address,
+		root_physical_address,
+		1,
+		PageTableEntryFlags::WRITABLE,
+	)?;
+
+	let root_pagetable = unsafe { &mut *(root_physical_address as *mut PageTable<RootLevel>) };
+	root_pagetable.zero();
+	root_pagetable.entries[RECURSIVE_INDEX]
+		.set(root_physical_address, PageTableEntryFlags::WRITABLE);
+
+	Ok(AddressSpace {
+		root_physical_address,
+	})
+}
+
+impl AddressSpace {
+	/// Returns the physical address of the root page table of this address space.
+	#[allow(dead_code)]
+	pub fn root_physical_address(&self) -> usize {
+		self.root_physical_address
+	}
+
+	/// Calls `f` with the root page table of this address space.
+	///
+	/// All page tables are accessed through the recursive mapping.
+	/// To reach the tables of this address space, the recursive entry of the active root page table is temporarily
+	/// redirected to our root page table, while all other translations stay untouched.
+	fn with_root_pagetable<R>(&self, f: impl FnOnce(&mut PageTable<RootLevel>) -> R) -> R {
+		let active_root_pagetable =
+			unsafe { &mut *(current_pml4_phys() as *mut PageTable<RootLevel>) };
+		let recursive_entry = active_root_pagetable.entries[RECURSIVE_INDEX];
+
+		active_root_pagetable.entries[RECURSIVE_INDEX]
+			.set(self.root_physical_address, PageTableEntryFlags::WRITABLE);
+		flush_tlb_all();
+
+		let ret = f(unsafe { &mut *ROOT_PAGETABLE_ADDRESS });
+
+		active_root_pagetable.entries[RECURSIVE_INDEX] = recursive_entry;
+		flush_tlb_all();
+
+		ret
+	}
+
+	/// Maps a continuous range of pages in this address space.
+	///
+	/// This mirrors [`map`], but does not require this address space to be active.
+	#[allow(dead_code)]
+	pub fn map<S: PageSize>(
+		&self,
+		virtual_address: usize,
+		physical_address: usize,
+		count: usize,
+		flags: PageTableEntryFlags,
+	) -> Result<(), MapError> {
+		let range = get_page_range::<S>(virtual_address, count);
+		self.with_root_pagetable(|root_pagetable| {
+			root_pagetable.map_pages(
+				range,
+				(physical_address..).step_by(S::SIZE),
+				flags,
+				&mut physicalmem::FrameAlloc,
+			)
+		})
+	}
+
+	/// Makes this the active address space by loading its root page table into CR3.
+	///
+	/// # Safety
+	///
+	/// The code calling this function, its stack, and all data it accesses afterwards must be mapped
+	/// identically in this address space.
+	pub unsafe fn activate(&self) {
+		asm!(
+			"mov cr3, {}",
+			in(reg) self.root_physical_address,
+			options(nostack, preserves_flags),
+		);
+	}
+}
+
+/// Makes the given address space the active one.
+///
+/// # Safety
+///
+/// Loading CR3 takes effect with the very next instruction.
+/// Thus, the code calling this function must be mapped identically in both the old and the new address space
+/// (for example, by an identity-mapped trampoline), as must be its stack and all data it accesses afterwards.
+/// In debug builds, this function asserts that at least the page of the current instruction is mapped in `new`.
+pub unsafe fn switch_address_space(new: &AddressSpace) {
+	if cfg!(debug_assertions) {
+		let instruction_pointer: usize;
+		asm!(
+			"lea {}, [rip]",
+			out(reg) instruction_pointer,
+			options(nomem, nostack, preserves_flags),
+		);
+
+		let page = Page::<BasePageSize>::including_address(instruction_pointer);
+		let is_mapped = new.with_root_pagetable(|root_pagetable| {
+			root_pagetable.get_page_table_entry(page).is_some()
+		});
+		assert!(
+			is_mapped,
+			"Current instruction at {:#x} is not mapped in the new address space",
+			instruction_pointer
+		);
+	}
+
+	new.activate();
+}
+
+/// Unmaps all pages below the given virtual address, such as the loader's low identity mapping.
+///
+/// Pages that extend beyond `below` are left untouched.
+/// Page tables that become empty are returned to the physical memory manager.
+#[allow(dead_code)]
+pub fn unmap_identity(below: usize) {
+	assert_eq!(
+		below % BasePageSize::SIZE,
+		0,
+		"Address is not on a 4 KiB page boundary (below = {:#x})",
+		below
+	);
+	assert!(
+		below <= LOWER_HALF_END,
+		"Address is not in the lower half of the address space (below = {:#x})",
+		below
+	);
+
+	let root_pagetable = ActiveTables::root();
+	root_pagetable.unmap_below(0, below);
+	flush_tlb_all();
+}
+
+/// Returns whether the given virtual address range lies completely in canonical address space.
+///
+/// That is, the range must lie either in the lower or in the upper half without touching the non-canonical hole
+/// in between.
+pub fn is_canonical_range(range: Range<usize>) -> bool {
+	let first = range.start;
+	let last = cmp::max(range.start, range.end.saturating_sub(1));
+
+	Page::<BasePageSize>::is_valid_address(first)
+		&& Page::<BasePageSize>::is_valid_address(last)
+		&& (last < LOWER_HALF_END || first >= LOWER_HALF_END)
+}
+
+/// Translates the given virtual address into the physical address it is mapped to.
+///
+/// Returns None if the address is not mapped.
+pub fn translate(virtual_address: usize) -> Option<usize> {
+	if !Page::<BasePageSize>::is_valid_address(virtual_address) {
+		return None;
+	}
+
+	let root_pagetable = ActiveTables::root();
+
+	// Walk down the hierarchy until we either hit a huge page or reach the PGT.
+	let page = Page::<HugePageSize>::including_address(virtual_address);
+	let entry = root_pagetable.get_page_table_entry(page)?;
+	if entry.is_huge() {
+		let frame = entry.page_address(HugePageSize::MAP_LEVEL);
+		return Some(frame | (virtual_address % HugePageSize::SIZE));
+	}
+
+	let page = Page::<LargePageSize>::including_address(virtual_address);
+	let entry = root_pagetable.get_page_table_entry(page)?;
+	if entry.is_huge() {
+		let frame = entry.page_address(LargePageSize::MAP_LEVEL);
+		return Some(frame | (virtual_address % LargePageSize::SIZE));
+	}
+
+	let page = Page::<BasePageSize>::including_address(virtual_address);
+	let entry = root_pagetable.get_page_table_entry(page)?;
+	Some(entry.address() | (virtual_address % BasePageSize::SIZE))
+}
+
+/// Returns the flags of the page containing the given virtual address, or None if it is not mapped.
+///
+/// If the address is part of a huge page, the flags of the huge page are returned.
+pub fn flags_of(virtual_address: usize) -> Option<PageTableEntryFlags> {
+	page_flags::<HugePageSize>(virtual_address)
+		.or_else(|| page_flags::<LargePageSize>(virtual_address))
+		.or_else(|| page_flags::<BasePageSize>(virtual_address))
+}
+
+/// A present leaf entry of the active page tables, as yielded by [`walk_mappings`].
+#[derive(Clone, Copy, Debug)]
+pub struct Mapping {
+	/// First virtual address of the mapping.
+	pub virtual_address: usize,
+
+	/// First physical address of the mapping.
+	pub physical_address: usize,
+
+	/// Size of the mapping in bytes, which is the size of the mapped page.
+	pub size: usize,
+
+	/// Flags of the page table entry.
+	pub flags: PageTableEntryFlags,
+}
+
+/// Iterator over all present leaf entries of the active page tables, as returned by [`walk_mappings`].
+pub struct Mappings {
+	/// For each level being walked: the address of the table, the index of its next entry and the first
+	/// virtual address it covers.
+	tables: [(usize, usize, usize); PAGE_LEVELS],
+
+	/// Level of the table currently being walked, where 0 is the PGT.
+	level: usize,
+}
+
+impl Iterator for Mappings {
+	type Item = Mapping;
+
+	fn next(&mut self) -> Option<Mapping> {
+		loop {
+			let (table_address, index, table_virtual_address) = self.tables[self.level];
+
+			if index == 1 << PAGE_MAP_BITS {
+				if self.level == PAGE_LEVELS - 1 {
+					return None;
+				}
+
+				self.level += 1;
+				continue;
+			}
+
+			self.tables[self.level].1 += 1;
+
+			// The recursive entry does not map any memory of its own.
+			if self.level == PAGE_LEVELS - 1 && index == RECURSIVE_INDEX {
+				continue;
+			}
+
+			let entry = unsafe { *(table_address as *const PageTableEntry).add(index) };
+			if !entry.is_present() {
+				continue;
+			}
+
+			let entry_size = 1 << (PAGE_BITS + self.level * PAGE_MAP_BITS);
+			let mut virtual_address = table_virtual_address + index * entry_size;
+			if virtual_address >= LOWER_HALF_END {
+				// Sign-extend the address to make it canonical.
+				virtual_address |= !(LOWER_HALF_END - 1);
+			}
+
+			if self.level == 0 || entry.is_huge() {
+				return Some(Mapping {
+					virtual_address,
+					physical_address: entry.page_address(self.level),
+					size: entry_size,
+					flags: entry.page_flags(self.level),
+				});
+			}
+
+			// Descend into the subtable.
+			let subtable_address = ActiveTables::subtable_address(table_address, index, entry);
+			self.level -= 1;
+			self.tables[self.level] = (subtable_address, 0, virtual_address);
+		}
+	}
+}
+
+/// Returns an iterator over all present leaf entries of the active page tables in ascending virtual address order.
+///
+/// Huge pages are yielded as single mappings of their respective size.
+#[allow(dead_code)]
+pub fn walk_mappings() -> Mappings {
+	let mut tables = [(0, 0, 0); PAGE_LEVELS];
+	tables[PAGE_LEVELS - 1] = (ActiveTables::root() as *mut _ as usize, 0, 0);
+
+	Mappings {
+		tables,
+		level: PAGE_LEVELS - 1,
+	}
+}
+
+/// Returns whether every page of the given range is mapped with pages of size S.
+///
+/// Missing subtables and pages mapped at a different level are reported as not mapped.
+#[allow(dead_code)]
+pub fn is_mapped<S: PageSize>(virtual_address: usize, count: usize) -> bool {
+	let root_pagetable = ActiveTables::root();
+	get_page_range::<S>(virtual_address, count).all(|page| {
+		root_pagetable
+			.get_page_table_entry_mut(page)
+			.map_or(false, |entry| entry.flags().contains(S::MAP_EXTRA_FLAG))
+	})
+}
+
+/// Installs a guard page at the 4 KiB page containing the given virtual address, so that any access faults.
+///
+/// The page table entry is reserved and marked with GUARD but is not present.
+/// Returns [`MapError::AlreadyMapped`] if the page is already mapped or guarded, also as part of a larger page.
+/// Mapping a page over a guard page fails with [`MapError::AlreadyMapped`] as well.
+pub fn map_guard_page(virtual_address: usize) -> Result<(), MapError> {
+	let page = Page::<BasePageSize>::including_address(virtual_address);
+	let root_pagetable = ActiveTables::root();
+	if root_pagetable.get_page_table_entry(page).is_some() {
+		return Err(MapError::AlreadyMapped);
+	}
+
+	root_pagetable.map_guard_page(page, &mut physicalmem::FrameAlloc)
+}
+
+/// Installs guard pages right below and right above the given region (see [`map_guard_page`]).
+#[allow(dead_code)]
+pub fn guard_region(virtual_address: usize, size: usize) -> Result<(), MapError> {
+	let start = align_down!(virtual_address, BasePageSize::SIZE);
+	let end = align_up!(virtual_address + size, BasePageSize::SIZE);
+	map_guard_page(start - BasePageSize::SIZE)?;
+	map_guard_page(end)
+}
+
+/// Maps a continuous range of pages to a single shared physical frame, such as a zero page.
+///
+/// The pages are always mapped read-only regardless of `flags`.
+/// That way, the first write to any of them faults, which the kernel may use to implement copy-on-write.
+#[allow(dead_code)]
+pub fn map_shared_frame<S: PageSize>(
+	virtual_address: usize,
+	count: usize,
+	physical_address: usize,
+	flags: PageTableEntryFlags,
+) -> Result<(), MapError> {
+	let mut flags = flags;
+	flags.remove(PageTableEntryFlags::WRITABLE);
+
+	let range = get_page_range::<S>(virtual_address, count);
+	let root_pagetable = ActiveTables::root();
+	for page in range {
+		if root_pagetable.map_page::<S, _>(
+			page,
+			physical_address,
+			flags,
+			&mut physicalmem::FrameAlloc,
+		)? {
+			page.flush_from_tlb();
+		}
+	}
+
+	Ok(())
+}
+
+/// Returns the number of page tables that have to be allocated for mapping a continuous range of pages.
+pub fn page_table_frames_needed<S: PageSize>(virtual_address: usize, count: usize) -> usize {
+	let range = get_page_range::<S>(virtual_address, count);
+	let root_pagetable = ActiveTables::root();
+	range
+		.enumerate()
+		.map(|(i, page)| root_pagetable.page_table_frames_needed::<S>(page, i == 0))
+		.sum()
+}
+
+/// Maps a continuous range of pages, either completely or not at all.
+///
+/// Before touching any entry, this verifies that the physical memory for all required page tables can be allocated.
+/// Otherwise, [`MapError::OutOfMemory`] is returned and the page tables are left unmodified.
+#[allow(dead_code)]
+pub fn map_checked<S: PageSize>(
+	virtual_address: usize,
+	physical_address: usize,
+	count: usize,
+	flags: PageTableEntryFlags,
+) -> Result<(), MapError> {
+	let frames = page_table_frames_needed::<S>(virtual_address, count);
+	if !physicalmem::can_allocate(frames * BasePageSize::SIZE) {
+		return Err(MapError::OutOfMemory);
+	}
+
+	map::<S>(virtual_address, physical_address, count, flags)
+}
+
+/// Maps a continuous range of pages like [`map`], but only if none of these pages is mapped yet.
+///
+/// Otherwise, [`MapError::AlreadyMapped`] is returned and the page tables are left unmodified.
+#[allow(dead_code)]
+pub fn map_new<S: PageSize>(
+	virtual_address: usize,
+	physical_address: usize,
+	count: usize,
+	flags: PageTableEntryFlags,
+) -> Result<(), MapError> {
+	let root_pagetable = ActiveTables::root();
+	if get_page_range::<S>(virtual_address, count).any(|page| {
+		root_pagetable.get_page_table_entry(page).is_some() || root_pagetable.is_guard_page(page)
+	}) {
+		return Err(MapError::AlreadyMapped);
+	}
+
+	map::<S>(virtual_address, physical_address, count, flags)
+}
+
+/// Flags selecting the memory type of a page through the Page Attribute Table.
+const MEMORY_TYPE_FLAGS: PageTableEntryFlags = PageTableEntryFlags::from_bits_truncate(
+	PageTableEntryFlags::WRITE_THROUGH.bits()
+		| PageTableEntryFlags::CACHE_DISABLE.bits()
+		| PageTableEntryFlags::PAT.bits()
+		| PageTableEntryFlags::PAT_HUGE.bits(),
+);
+
+/// Maps a continuous range of pages like [`map`], but keeps some flags of pages of the same size that are already
+/// mapped.
+///
+/// GLOBAL is kept if it was set before.
+/// The memory type (WRITE_THROUGH, CACHE_DISABLE, PAT, and PAT_HUGE) is kept unless `flags` selects one itself.
+/// All other flags are replaced by `flags` as with [`map`].
+#[allow(dead_code)]
+pub fn map_preserving<S: PageSize>(
+	virtual_address: usize,
+	physical_address: usize,
+	count: usize,
+	flags: PageTableEntryFlags,
+) -> Result<(), MapError> {
+	let range = get_page_range::<S>(virtual_address, count);
+	let root_pagetable = ActiveTables::root();
+	let mut current_physical_address = physical_address;
+	for page in range {
+		let mut page_flags = flags;
+		if let Some(entry) = root_pagetable
+			.get_page_table_entry_mut::<S>(page)
+			.filter(|entry| S::MAP_LEVEL == 0 || entry.is_huge())
+		{
+			let mut old_flags = entry.page_flags(S::MAP_LEVEL);
+			// HUGE_PAGE shares its bit with PAT, which is part of the memory type of 4 KiB pages only.
+			if S::MAP_LEVEL > 0 {
+				old_flags.remove(PageTableEntryFlags::HUGE_PAGE);
+			}
+
+			page_flags |= old_flags & PageTableEntryFlags::GLOBAL;
+			if !flags.intersects(MEMORY_TYPE_FLAGS) {
+				page_flags |= old_flags & MEMORY_TYPE_FLAGS;
+			}
+		}
+
+		if root_pagetable.map_page::<S, _>(
+			page,
+			current_physical_address,
+			page_flags,
+			&mut physicalmem::FrameAlloc,
+		)? {
+			page.flush_from_tlb();
+		}
+		current_physical_address += S::SIZE;
+	}
+
+	Ok(())
+}
+
+/// Unmaps a continuous range of pages, which all have to be mapped.
+/// Page tables that become empty are freed, except for the root page table.
+///
+/// Returns the number of page table entries that have been cleared.
+pub fn unmap<S: PageSize>(virtual_address: usize, count: usize) -> usize {
+	let range = get_page_range::<S>(virtual_address, count);
+	let root_pagetable = ActiveTables::root();
+	let mut cleared = 0;
+	for page in range {
+		root_pagetable.unmap_page::<S>(page);
+		cleared += 1;
+	}
+
+	cleared
+}
+
+/// Moves a continuous range of mapped pages from `old_virtual_address` to `new_virtual_address`.
+///
+/// Each page keeps its physical address and flags. The old pages are unmapped afterwards,
+/// freeing page tables that become empty. Both ranges must not overlap.
+#[allow(dead_code)]
+pub fn remap<S: PageSize>(
+	old_virtual_address: usize,
+	new_virtual_address: usize,
+	count: usize,
+) -> Result<(), MapError> {
+	let size = count * S::SIZE;
+	assert!(
+		old_virtual_address + size <= new_virtual_address
+			|| new_virtual_address + size <= old_virtual_address,
+		"Trying to remap {:#x} to the overlapping address {:#x}",
+		old_virtual_address,
+		new_virtual_address
+	);
+
+	let old_range = get_page_range::<S>(old_virtual_address, count);
+	let new_range = get_page_range::<S>(new_virtual_address, count);
+	let root_pagetable = ActiveTables::root();
+	for (old_page, new_page) in old_range.zip(new_range) {
+		let entry = root_pagetable
+			.get_page_table_entry_mut::<S>(old_page)
+			.unwrap_or_else(|| {
+				panic!(
+					"Trying to remap page {:#x}, which is not mapped",
+					old_page.virtual_address
+				)
+			});
+		let physical_address = entry.page_address(S::MAP_LEVEL);
+		let flags = entry.page_flags(S::MAP_LEVEL);
+
+		if root_pagetable.map_page::<S, _>(
+			new_page,
+			physical_address,
+			flags,
+			&mut physicalmem::FrameAlloc,
+		)? {
+			new_page.flush_from_tlb();
+		}
+		root_pagetable.unmap_page::<S>(old_page);
+	}
+
+	Ok(())
+}
+
+/// Changes the flags of a continuous range of mapped pages without changing their physical addresses.
+///
+/// The PRESENT, ACCESSED, and DIRTY flags of each page are preserved, while all other flags are replaced by `flags`.
+pub fn protect<S: PageSize>(virtual_address: usize, count: usize, flags: PageTableEntryFlags) {
+	let preserved_flags = PageTableEntryFlags::PRESENT
+		| PageTableEntryFlags::ACCESSED
+		| PageTableEntryFlags::DIRTY
+		| S::MAP_EXTRA_FLAG;
+
+	let range = get_page_range::<S>(virtual_address, count);
+	let root_pagetable = ActiveTables::root();
+	for page in range {
+		let entry = root_pagetable
+			.get_page_table_entry_mut::<S>(page)
+			.unwrap_or_else(|| {
+				panic!(
+					"Trying to protect page {:#x}, which is not mapped",
+					page.virtual_address
+				)
+			});
+
+		let flags = (entry.page_flags(S::MAP_LEVEL) & preserved_flags) | flags;
+		entry.physical_address_and_flags = entry.page_address(S::MAP_LEVEL) | flags.bits();
+		page.flush_from_tlb();
+	}
+}
+
+/// Splits the 2 MiB page containing the given virtual address into 512 4 KiB pages with the same flags,
+/// which map the same physical memory.
+///
+/// Does nothing if the address is already mapped with 4 KiB pages.
+pub fn split_large_page(virtual_address: usize) {
+	let page = Page::<LargePageSize>::including_address(virtual_address);
+	let root_pagetable = ActiveTables::root();
+	let entry = root_pagetable
+		.get_page_table_entry_mut::<LargePageSize>(page)
+		.unwrap_or_else(|| {
+			panic!(
+				"Trying to split page {:#x}, which is not mapped",
+				page.virtual_address
+			)
+		});
+	if !entry.is_huge() {
+		return;
+	}
+
+	let frame = entry.page_address(LargePageSize::MAP_LEVEL);
+	let mut flags = entry.page_flags(LargePageSize::MAP_LEVEL);
+	// 4 KiB pages select the upper half of the PAT with PAT, which shares its bit with HUGE_PAGE.
+	flags.remove(PageTableEntryFlags::HUGE_PAGE);
+	if flags.contains(PageTableEntryFlags::PAT_HUGE) {
+		flags.remove(PageTableEntryFlags::PAT_HUGE);
+		flags.insert(PageTableEntryFlags::PAT);
+	}
+
+	// The new PGT is filled through its identity mapping before it replaces the 2 MiB page,
+	// so the range never translates through uninitialized entries.
+	let table_physical_address = physicalmem::allocate_zeroed(BasePageSize::SIZE);
+	let table = unsafe { &mut *(table_physical_address as *mut PageTable<PGT>) };
+	for (index, entry) in table.entries.iter_mut().enumerate() {
+		entry.set(frame + index * BasePageSize::SIZE, flags);
+	}
+
+	// Mapping the PGT may have created page tables, so the entry is looked up again.
+	let entry = root_pagetable
+		.get_page_table_entry_mut::<LargePageSize>(page)
+		.unwrap();
+	entry.set(table_physical_address, PageTableEntryFlags::WRITABLE);
+
+	// The new PGT is accessible through the recursive mapping, which may still refer to the 2 MiB page in the TLB.
+	let table_address = (entry as *mut PageTableEntry as usize) << PAGE_MAP_BITS;
+	unsafe {
+		asm!("invlpg [{}]", in(reg) table_address, options(nostack, preserves_flags));
+	}
+
+	// Translations of the whole 2 MiB range may still be cached.
+	let count = LargePageSize::SIZE / BasePageSize::SIZE;
+	for small_page in get_page_range::<BasePageSize>(page.virtual_address, count) {
+		small_page.flush_from_tlb();
+	}
+}
+
+/// Returns the flags of the page of size S containing the given virtual address,
+/// or None if it is not mapped with a page of this size.
+fn page_flags<S: PageSize>(virtual_address: usize) -> Option<PageTableEntryFlags> {
+	if !Page::<S>::is_valid_address(virtual_address) {
+		return None;
+	}
+
+	let page = Page::<S>::including_address(virtual_address);
+	let root_pagetable = ActiveTables::root();
+	root_pagetable
+		.get_page_table_entry_mut::<S>(page)
+		.filter(|entry| S::MAP_LEVEL == 0 || entry.is_huge())
+		.map(|entry| entry.page_flags(S::MAP_LEVEL))
+}
+
+/// Returns whether the page of size S containing the given virtual address has been accessed,
+/// or None if it is not mapped with a page of this size.
+#[allow(dead_code)]
+pub fn page_accessed<S: PageSize>(virtual_address: usize) -> Option<bool> {
+	page_flags::<S>(virtual_address).map(|flags| flags.contains(PageTableEntryFlags::ACCESSED))
+}
+
+/// Returns whether the page of size S containing the given virtual address has been written to,
+/// or None if it is not mapped with a page of this size.
+#[allow(dead_code)]
+pub fn page_dirty<S: PageSize>(virtual_address: usize) -> Option<bool> {
+	page_flags::<S>(virtual_address).map(|flags| flags.contains(PageTableEntryFlags::DIRTY))
+}
+
+/// Clears the ACCESSED and DIRTY flags of a continuous range of mapped pages and flushes them from the TLB.
+#[allow(dead_code)]
+pub fn clear_accessed_dirty<S: PageSize>(virtual_address: usize, count: usize) {
+	let cleared_flags = PageTableEntryFlags::ACCESSED | PageTableEntryFlags::DIRTY;
+
+	let range = get_page_range::<S>(virtual_address, count);
+	let root_pagetable = ActiveTables::root();
+	for page in range {
+		let entry = root_pagetable
+			.get_page_table_entry_mut::<S>(page)
+			.unwrap_or_else(|| {
+				panic!(
+					"Trying to clear the accessed and dirty flags of page {:#x}, which is not mapped",
+					page.virtual_address
+				)
+			});
+
+		entry.physical_address_and_flags &= !cleared_flags.bits();
+		page.flush_from_tlb();
+	}
 }
 
 pub fn map<S: PageSize>(
@@ -435,8 +1915,517 @@ pub fn map<S: PageSize>(
 	physical_address: usize,
 	count: usize,
 	flags: PageTableEntryFlags,
-) {
+) -> Result<(), MapError> {
+	map_with::<S, _>(
+		virtual_address,
+		physical_address,
+		count,
+		flags,
+		&mut physicalmem::FrameAlloc,
+	)
+}
+
+/// Maps a range of pages like [`map`].
+#[allow(dead_code)]
+pub fn map_range<S: PageSize>(
+	range: PageRange<S>,
+	physical_address: usize,
+	flags: PageTableEntryFlags,
+) -> Result<(), MapError> {
+	if range.count() == 0 {
+		return Ok(());
+	}
+
+	map::<S>(range.start(), physical_address, range.count(), flags)
+}
+
+/// Maps a continuous range of pages like [`map`], but allocates missing page tables from `allocator`.
+pub fn map_with<S: PageSize, A: PageTableAllocator>(
+	virtual_address: usize,
+	physical_address: usize,
+	count: usize,
+	flags: PageTableEntryFlags,
+	allocator: &mut A,
+) -> Result<(), MapError> {
 	let range = get_page_range::<S>(virtual_address, count);
-	let root_pagetable = unsafe { &mut *PML4_ADDRESS };
-	root_pagetable.map_pages(range, physical_address, flags);
+	let root_pagetable = ActiveTables::root();
+	root_pagetable.map_pages(
+		range,
+		(physical_address..).step_by(S::SIZE),
+		flags,
+		allocator,
+	)
+}
+
+/// Maps a continuous range of pages starting at `virtual_address` to the given frames, which need not be
+/// physically contiguous.
+///
+/// Each successive page is mapped to the next physical address from `frames`, which must be aligned to `S::SIZE`.
+pub fn map_frames<S: PageSize, I: ExactSizeIterator<Item = usize>>(
+	virtual_address: usize,
+	frames: I,
+	flags: PageTableEntryFlags,
+) -> Result<(), MapError> {
+	if frames.len() == 0 {
+		return Ok(());
+	}
+
+	let range = get_page_range::<S>(virtual_address, frames.len());
+	let root_pagetable = ActiveTables::root();
+	root_pagetable.map_pages(range, frames, flags, &mut physicalmem::FrameAlloc)
+}
+
+/// Maps a continuous range of memory, choosing the largest page sizes possible.
+///
+/// The unaligned head and tail of the range are mapped with 4 KiB pages and the rest with 2 MiB pages,
+/// or 1 GiB pages if supported by the CPU.
+/// A page size is only used where both the virtual and the physical address are aligned to it.
+///
+/// # Arguments
+///
+/// * `virtual_address` - First virtual address of the range, must be 4 KiB aligned
+/// * `physical_address` - First physical address of the range, must be 4 KiB aligned
+/// * `size` - Size of the range in bytes, which is rounded up to 4 KiB
+/// * `flags` - Flags for all pages of the range
+#[allow(dead_code)]
+pub fn map_region(
+	virtual_address: usize,
+	physical_address: usize,
+	size: usize,
+	flags: PageTableEntryFlags,
+) -> Result<(), MapError> {
+	assert_eq!(
+		virtual_address % BasePageSize::SIZE,
+		0,
+		"Virtual address {:#x} is not 4 KiB aligned",
+		virtual_address
+	);
+	assert_eq!(
+		physical_address % BasePageSize::SIZE,
+		0,
+		"Physical address {:#x} is not 4 KiB aligned",
+		physical_address
+	);
+
+	let end = virtual_address + align_up!(size, BasePageSize::SIZE);
+	let huge_page_size = has_huge_pages().then(|| HugePageSize::SIZE);
+	let mut virtual_address = virtual_address;
+	let mut physical_address = physical_address;
+
+	while virtual_address < end {
+		let fits = |page_size: usize| {
+			virtual_address % page_size == 0
+				&& physical_address % page_size == 0
+				&& end - virtual_address >= page_size
+		};
+
+		let mapped = if huge_page_size.is_some() && fits(HugePageSize::SIZE) {
+			let count = pages_until::<HugePageSize>(virtual_address, physical_address, end, None);
+			map::<HugePageSize>(virtual_address, physical_address, count, flags)?;
+			count * HugePageSize::SIZE
+		} else if fits(LargePageSize::SIZE) {
+			let count = pages_until::<LargePageSize>(
+				virtual_address,
+				physical_address,
+				end,
+				huge_page_size,
+			);
+			map::<LargePageSize>(virtual_address, physical_address, count, flags)?;
+			count * LargePageSize::SIZE
+		} else {
+			let count = pages_until::<BasePageSize>(
+				virtual_address,
+				physical_address,
+				end,
+				Some(LargePageSize::SIZE),
+			);
+			map::<BasePageSize>(virtual_address, physical_address, count, flags)?;
+			count * BasePageSize::SIZE
+		};
+
+		virtual_address += mapped;
+		physical_address += mapped;
+	}
+
+	Ok(())
+}
+
+/// Returns how many pages of size S can be mapped at the given addresses before either reaching `end`
+/// or an address from which on a whole page of `next_size` could be mapped instead.
+fn pages_until<S: PageSize>(
+	virtual_address: usize,
+	physical_address: usize,
+	end: usize,
+	next_size: Option<usize>,
+) -> usize {
+	let mut limit = end;
+
+	if let Some(next_size) = next_size {
+		// Larger pages can only ever be used if both addresses have the same offset into them.
+		if virtual_address % next_size == physical_address % next_size {
+			let boundary = align_up!(virtual_address, next_size);
+			if boundary + next_size <= end {
+				limit = boundary;
+			}
+		}
+	}
+
+	(limit - virtual_address) / S::SIZE
+}
+
+/// Returns whether the CPU supports 1 GiB pages.
+fn has_huge_pages() -> bool {
+	unsafe {
+		// CPUID.80000001H:EDX.Page1GB[bit 26]
+		__get_cpuid_max(0x8000_0000).0 >= 0x8000_0001 && __cpuid(0x8000_0001).edx & (1 << 26) != 0
+	}
+}
+
+/// Enables [`PageTableEntryFlags::EXECUTE_DISABLE`] by setting IA32_EFER.NXE if the CPU supports it.
+///
+/// Returns whether the flag may be used.
+/// Without NXE, the flag is a reserved bit and causes page faults.
+pub fn enable_execute_disable() -> bool {
+	// CPUID.80000001H:EDX.NX[bit 20]
+	let supported = unsafe {
+		__get_cpuid_max(0x8000_0000).0 >= 0x8000_0001 && __cpuid(0x8000_0001).edx & (1 << 20) != 0
+	};
+	if !supported {
+		return false;
+	}
+
+	unsafe {
+		asm!(
+			"rdmsr",
+			"or eax, {nxe}",
+			"wrmsr",
+			nxe = const EFER_NXE,
+			in("ecx") IA32_EFER,
+			out("eax") _,
+			out("edx") _,
+			options(nostack),
+		);
+	}
+	true
+}
+
+/// Restricts valid virtual addresses to the linear address width reported by the CPU.
+///
+/// Without this, addresses are checked against the width translated by the paging mode.
+pub fn init_linear_address_width() {
+	// CPUID.80000008H:EAX[bits 15-8] is the linear address width.
+	let bits = unsafe {
+		if __get_cpuid_max(0x8000_0000).0 < 0x8000_0008 {
+			return;
+		}
+		(__cpuid(0x8000_0008).eax >> 8) & 0xFF
+	};
+	if bits == 0 {
+		return;
+	}
+
+	let sign_bit = cmp::min(bits as usize - 1, MAX_SIGN_BIT);
+	set_linear_address_width(sign_bit);
+	loaderlog!("Linear address width: {} bits", sign_bit + 1);
+}
+
+/// Sets the most significant implemented bit of a linear address (47 for 48-bit, 56 for 57-bit addresses).
+///
+/// Virtual addresses whose bits 63 through `sign_bit` are not all equal are rejected afterwards.
+pub fn set_linear_address_width(sign_bit: usize) {
+	assert!(
+		(PAGE_BITS..=MAX_SIGN_BIT).contains(&sign_bit),
+		"Linear address width of {} bits is not supported",
+		sign_bit + 1
+	);
+	SIGN_BIT.store(sign_bit, Ordering::Relaxed);
+}
+
+/// Programs the Page Attribute Table to the layout described by [`PAT_LAYOUT`].
+///
+/// Must be called before mapping memory with [`PageTableEntryFlags::write_combining`].
+pub fn init_pat() {
+	unsafe {
+		asm!(
+			"wrmsr",
+			in("ecx") IA32_PAT,
+			in("eax") PAT_LAYOUT as u32,
+			in("edx") (PAT_LAYOUT >> 32) as u32,
+			options(nostack, preserves_flags),
+		);
+	}
+
+	// Cached translations may still refer to the old memory types.
+	flush_tlb_all();
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::alloc::{alloc_zeroed, Layout};
+	use std::cell::Cell;
+	use std::collections::BTreeMap;
+
+	std::thread_local! {
+		/// Address of the root page table of [`HostTables`] of this test thread.
+		static ROOT: Cell<usize> = Cell::new(0);
+	}
+
+	/// Page tables in host memory, whose frames are accessed through their physical addresses.
+	///
+	/// Each test thread operates on its own tables, which are created by [`HostTables::init`].
+	pub(super) enum HostTables {}
+
+	impl HostTables {
+		/// Replaces the page tables of this test thread with an empty root page table.
+		fn init() {
+			let root = HostFrameAlloc.allocate_frame().unwrap();
+			ROOT.with(|cell| cell.set(root));
+		}
+	}
+
+	impl PageTableMemory for HostTables {
+		fn root() -> &'static mut PageTable<RootLevel> {
+			let root = ROOT.with(Cell::get);
+			assert_ne!(root, 0, "HostTables::init has not been called");
+			unsafe { &mut *(root as *mut PageTable<RootLevel>) }
+		}
+
+		fn subtable_address(_table_address: usize, _index: usize, entry: PageTableEntry) -> usize {
+			entry.address()
+		}
+
+		fn flush_page(_virtual_address: usize) {}
+	}
+
+	/// Allocates zeroed frames for [`HostTables`] from the host's heap, which are never freed.
+	struct HostFrameAlloc;
+
+	impl PageTableAllocator for HostFrameAlloc {
+		fn allocate_frame(&mut self) -> Option<usize> {
+			let layout = Layout::from_size_align(BasePageSize::SIZE, BasePageSize::SIZE).unwrap();
+			let frame = unsafe { alloc_zeroed(layout) } as usize;
+			(frame != 0).then_some(frame)
+		}
+	}
+
+	/// A xorshift generator, which makes the randomized tests reproducible.
+	struct Rng(u64);
+
+	impl Rng {
+		fn next(&mut self) -> usize {
+			self.0 ^= self.0 << 13;
+			self.0 ^= self.0 >> 7;
+			self.0 ^= self.0 << 17;
+			self.0 as usize
+		}
+
+		/// Returns a random multiple of `align` below `end`.
+		fn aligned_below(&mut self, end: usize, align: usize) -> usize {
+			align_down!(self.next() % end, align)
+		}
+	}
+
+	/// Flags that are stored as they are in leaf entries of all levels.
+	const RANDOM_FLAGS: [PageTableEntryFlags; 5] = [
+		PageTableEntryFlags::WRITABLE,
+		PageTableEntryFlags::USER_ACCESSIBLE,
+		PageTableEntryFlags::WRITE_THROUGH,
+		PageTableEntryFlags::CACHE_DISABLE,
+		PageTableEntryFlags::EXECUTE_DISABLE,
+	];
+
+	/// A page mapped by [`map_random_pages`].
+	struct RandomPage {
+		physical_address: usize,
+		size: usize,
+		flags: PageTableEntryFlags,
+	}
+
+	/// Maps `count` random, non-overlapping 4 KiB and 2 MiB pages in the lower half with random flags.
+	///
+	/// Returns the mapped pages by their virtual addresses.
+	fn map_random_pages(rng: &mut Rng, count: usize) -> BTreeMap<usize, RandomPage> {
+		// Pages are confined to a few 2 MiB regions, so that they share subtables.
+		const VIRTUAL_END: usize = 1 << 34;
+		const PHYSICAL_END: usize = 1 << 46;
+
+		let mut pages = BTreeMap::new();
+		// Whether each used 2 MiB region contains a 2 MiB page or 4 KiB pages.
+		let mut regions = BTreeMap::new();
+		while pages.len() < count {
+			let size = if rng.next() % 4 == 0 {
+				LargePageSize::SIZE
+			} else {
+				BasePageSize::SIZE
+			};
+			let virtual_address = rng.aligned_below(VIRTUAL_END, size);
+			let physical_address = rng.aligned_below(PHYSICAL_END, size);
+			let flags = RANDOM_FLAGS
+				.into_iter()
+				.filter(|_| rng.next() % 2 == 0)
+				.fold(PageTableEntryFlags::empty(), |flags, flag| flags | flag);
+
+			let region = align_down!(virtual_address, LargePageSize::SIZE);
+			let is_large = size == LargePageSize::SIZE;
+			if *regions.entry(region).or_insert(is_large) != is_large
+				|| pages.contains_key(&virtual_address)
+			{
+				continue;
+			}
+
+			let result = if is_large {
+				map_with::<LargePageSize, _>(
+					virtual_address,
+					physical_address,
+					1,
+					flags,
+					&mut HostFrameAlloc,
+				)
+			} else {
+				map_with::<BasePageSize, _>(
+					virtual_address,
+					physical_address,
+					1,
+					flags,
+					&mut HostFrameAlloc,
+				)
+			};
+			result.unwrap();
+
+			pages.insert(
+				virtual_address,
+				RandomPage {
+					physical_address,
+					size,
+					flags,
+				},
+			);
+		}
+
+		pages
+	}
+
+	#[test]
+	fn translate_round_trips_random_mappings() {
+		HostTables::init();
+		let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+		let pages = map_random_pages(&mut rng, 1000);
+
+		for (&virtual_address, page) in &pages {
+			let offset = rng.next() % page.size;
+			assert_eq!(
+				translate(virtual_address + offset),
+				Some(page.physical_address + offset),
+				"{virtual_address:#x} + {offset:#x}"
+			);
+
+			let flags = flags_of(virtual_address).unwrap();
+			let random_flags = RANDOM_FLAGS
+				.into_iter()
+				.fold(PageTableEntryFlags::empty(), |flags, flag| flags | flag);
+			assert_eq!(flags & random_flags, page.flags, "{virtual_address:#x}");
+			assert!(flags.contains(PageTableEntryFlags::PRESENT));
+		}
+	}
+
+	#[test]
+	fn is_mapped_agrees_with_random_mappings() {
+		HostTables::init();
+		let mut rng = Rng(0xD1B5_4A32_D192_ED03);
+		let pages = map_random_pages(&mut rng, 1000);
+
+		for (&virtual_address, page) in &pages {
+			let is_large = page.size == LargePageSize::SIZE;
+			assert_eq!(is_mapped::<LargePageSize>(virtual_address, 1), is_large);
+			assert_eq!(is_mapped::<BasePageSize>(virtual_address, 1), !is_large);
+		}
+
+		// Pages that have not been mapped, also next to mapped ones.
+		for _ in 0..1000 {
+			let virtual_address = rng.aligned_below(1 << 35, BasePageSize::SIZE);
+			let is_covered = pages
+				.range(..=virtual_address)
+				.next_back()
+				.map_or(false, |(&start, page)| virtual_address < start + page.size);
+			assert_eq!(translate(virtual_address).is_some(), is_covered);
+			if !is_covered {
+				assert!(!is_mapped::<BasePageSize>(virtual_address, 1));
+				assert!(!is_mapped::<LargePageSize>(virtual_address, 1));
+			}
+		}
+	}
+
+	#[test]
+	fn table_index_extracts_nine_bits_per_level() {
+		// The PML4 index has its most significant bit set, so the address has to be sign-extended.
+		let virtual_address =
+			0xFFFF_0000_0000_0000 | 0x1A5 << 39 | 0x0F3 << 30 | 0x100 << 21 | 0x0FF << 12 | 0xABC;
+		let page = Page::<BasePageSize>::including_address(virtual_address);
+
+		assert_eq!(page.table_index::<PML4>(), 0x1A5);
+		assert_eq!(page.table_index::<PDPT>(), 0x0F3);
+		assert_eq!(page.table_index::<PDT>(), 0x100);
+		assert_eq!(page.table_index::<PGT>(), 0x0FF);
+	}
+
+	#[test]
+	fn page_range_is_inclusive() {
+		let pages = get_page_range::<BasePageSize>(0x1234, 3).collect::<Vec<_>>();
+		let addresses = pages
+			.iter()
+			.map(|page| page.virtual_address)
+			.collect::<Vec<_>>();
+		assert_eq!(addresses, [0x1000, 0x2000, 0x3000]);
+
+		assert_eq!(get_page_range::<LargePageSize>(0x20_0000, 1).count(), 1);
+		assert_eq!(get_page_range::<LargePageSize>(0x20_0000, 512).count(), 512);
+	}
+
+	#[test]
+	fn non_canonical_addresses_are_rejected() {
+		let is_valid_address = Page::<BasePageSize>::is_valid_address;
+
+		assert!(is_valid_address(0));
+		assert!(is_valid_address((1 << MAX_SIGN_BIT) - 1));
+		assert!(is_valid_address(usize::MAX << MAX_SIGN_BIT));
+		assert!(is_valid_address(usize::MAX));
+
+		assert!(!is_valid_address(1 << MAX_SIGN_BIT));
+		assert!(!is_valid_address((usize::MAX << MAX_SIGN_BIT) - 1));
+		assert!(!is_valid_address(1 << 63));
+	}
+
+	#[test]
+	fn pat_huge_is_not_part_of_the_address() {
+		let mut entry = PageTableEntry {
+			physical_address_and_flags: 0,
+		};
+		let flags = PageTableEntryFlags::HUGE_PAGE
+			| PageTableEntryFlags::write_combining::<LargePageSize>();
+		entry.set(0x4020_0000, flags);
+
+		assert_eq!(entry.page_address(LargePageSize::MAP_LEVEL), 0x4020_0000);
+		assert!(entry
+			.page_flags(LargePageSize::MAP_LEVEL)
+			.contains(PageTableEntryFlags::PAT_HUGE));
+
+		// In a PGT, bit 12 is part of the address.
+		entry.set(0x4020_1000, PageTableEntryFlags::empty());
+		assert_eq!(entry.page_address(BasePageSize::MAP_LEVEL), 0x4020_1000);
+		assert!(!entry
+			.page_flags(BasePageSize::MAP_LEVEL)
+			.contains(PageTableEntryFlags::PAT_HUGE));
+	}
+
+	#[test]
+	fn recursive_subtable_address_appends_index() {
+		let root = ROOT_PAGETABLE_ADDRESS as usize;
+
+		assert_eq!(recursive_subtable_address(root, 0), 0xFFFF_FFFF_FFE0_0000);
+		assert_eq!(
+			recursive_subtable_address(root, 0x1A5),
+			0xFFFF_FFFF_FFFA_5000
+		);
+	}
 }
@@ -1,14 +1,284 @@
-use crate::arch::paging::{BasePageSize, PageSize};
+use core::ops::Range;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-static mut CURRENT_ADDRESS: usize = 0;
+use crate::arch::paging::{self, BasePageSize, PageSize, PageTableAllocator, PageTableEntryFlags};
 
-pub fn init(address: usize) {
-	unsafe {
-		CURRENT_ADDRESS = address;
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Maximum number of physical memory regions, see [`add_region`].
+const MAX_REGIONS: usize = 16;
+
+/// A range of physical memory from `start` up to (excluding) `end`, which is handed out from `current` upwards.
+struct Region {
+	start: AtomicUsize,
+	current: AtomicUsize,
+	end: AtomicUsize,
+}
+
+impl Region {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const EMPTY: Region = Region {
+		start: AtomicUsize::new(0),
+		current: AtomicUsize::new(0),
+		end: AtomicUsize::new(0),
+	};
+
+	fn contains(&self, address: usize) -> bool {
+		(self.start.load(Ordering::Relaxed)..self.end.load(Ordering::Relaxed)).contains(&address)
 	}
 }
 
+/// The physical memory regions registered with [`add_region`], in the order they are allocated from.
+static REGIONS: [Region; MAX_REGIONS] = [Region::EMPTY; MAX_REGIONS];
+
+/// Number of registered [`REGIONS`].
+static REGION_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Index of the region that is currently allocated from.
+///
+/// The remaining memory of earlier regions is skipped, so allocations are always in region order.
+static CURRENT_REGION: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of bytes that have been skipped to satisfy alignment requests.
+static ALIGNMENT_WASTE: AtomicUsize = AtomicUsize::new(0);
+
+/// Maximum number of freed frames that are kept for reuse.
+const MAX_FREE_FRAMES: usize = 512;
+
+/// Frames that have been freed and can be handed out again, where 0 marks an unused slot.
+///
+/// Freed memory is generally not mapped in the loader and may be unmapped by [`paging::unmap_identity`] at any time,
+/// so the frames are not linked through themselves.
+/// Mapping them when freeing would also re-enter the paging code, which frees page tables through [`deallocate`].
+static FREE_FRAMES: [AtomicUsize; MAX_FREE_FRAMES] = {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const UNUSED: AtomicUsize = AtomicUsize::new(0);
+	[UNUSED; MAX_FREE_FRAMES]
+};
+
+/// Number of bytes that have been freed while [`FREE_FRAMES`] was full and are lost.
+static DISCARDED: AtomicUsize = AtomicUsize::new(0);
+
+/// The default allocator for page tables, which allocates frames with [`try_allocate`].
+pub struct FrameAlloc;
+
+impl PageTableAllocator for FrameAlloc {
+	fn allocate_frame(&mut self) -> Option<usize> {
+		try_allocate(BasePageSize::SIZE)
+	}
+}
+
+/// Usage statistics of the physical memory manager.
+#[derive(Clone, Copy, Debug)]
+pub struct Stats {
+	/// Number of bytes handed out since initialization, including alignment waste.
+	pub used: usize,
+
+	/// Number of bytes that have been skipped to satisfy alignment requests and are lost.
+	pub alignment_waste: usize,
+
+	/// Number of bytes that have been freed but could not be kept for reuse and are lost.
+	pub discarded: usize,
+}
+
+/// A saved state of the Physical Memory Manager, see [`mark`].
+#[derive(Debug)]
+pub struct Watermark {
+	region: usize,
+	current_address: usize,
+	alignment_waste: usize,
+	discarded: usize,
+}
+
+/// Returns the registered regions.
+fn regions() -> &'static [Region] {
+	&REGIONS[..REGION_COUNT.load(Ordering::Acquire)]
+}
+
+/// Returns the index of the region containing `address`, if any.
+fn region_of(address: usize) -> Option<usize> {
+	regions().iter().position(|region| region.contains(address))
+}
+
+/// Returns whether `address` lies within the physical memory managed by the Physical Memory Manager.
+pub fn is_managed(address: usize) -> bool {
+	region_of(address).is_some()
+}
+
+/// Registers the physical memory from `start` up to (excluding) `end` with the Physical Memory Manager.
+///
+/// Regions are allocated from in the order they have been added.
+/// Once an allocation does not fit into a region anymore, it is taken from the next region that can satisfy it
+/// and the remaining memory of the earlier regions is skipped.
+pub fn add_region(start: usize, end: usize) {
+	assert!(
+		start < end,
+		"Physical memory range [{:#x} - {:#x}] is empty",
+		start,
+		end
+	);
+	assert!(
+		regions()
+			.iter()
+			.all(|region| end <= region.start.load(Ordering::Relaxed)
+				|| region.end.load(Ordering::Relaxed) <= start),
+		"Physical memory range [{:#x} - {:#x}] overlaps an existing region",
+		start,
+		end
+	);
+
+	let index = REGION_COUNT.load(Ordering::Relaxed);
+	assert!(
+		index < MAX_REGIONS,
+		"Too many physical memory regions ({} regions)",
+		MAX_REGIONS
+	);
+
+	let region = &REGIONS[index];
+	region.start.store(start, Ordering::Relaxed);
+	region.current.store(start, Ordering::Relaxed);
+	region.end.store(end, Ordering::Relaxed);
+	REGION_COUNT.store(index + 1, Ordering::Release);
+	INITIALIZED.store(true, Ordering::Release);
+}
+
 pub fn allocate(size: usize) -> usize {
+	try_allocate(size).unwrap_or_else(|| {
+		panic!(
+			"Could not allocate {:#x} B of physical memory: out of memory",
+			size
+		)
+	})
+}
+
+/// Allocates `size` bytes and fills them with zeros.
+///
+/// Freshly allocated memory is generally not mapped in the loader, so it is identity-mapped as writable for zeroing
+/// and stays mapped afterwards.
+pub fn allocate_zeroed(size: usize) -> usize {
+	let address = allocate(size);
+	paging::map::<BasePageSize>(
+		address,
+		address,
+		size / BasePageSize::SIZE,
+		PageTableEntryFlags::WRITABLE,
+	)
+	.unwrap_or_else(|err| panic!("Could not map {address:#x} for zeroing it: {err:?}"));
+	unsafe {
+		ptr::write_bytes(address as *mut u8, 0, size);
+	}
+	address
+}
+
+/// Allocates `size` bytes, or returns None if there is not enough physical memory left.
+///
+/// Single frames are preferably taken from the frames that have been freed before.
+pub fn try_allocate(size: usize) -> Option<usize> {
+	if size == BasePageSize::SIZE {
+		if let Some(address) = pop_free_frame() {
+			return Some(address);
+		}
+	}
+
+	try_allocate_aligned(size, BasePageSize::SIZE)
+}
+
+/// Takes a frame from the freed frames, if there is any.
+fn pop_free_frame() -> Option<usize> {
+	FREE_FRAMES.iter().find_map(|slot| {
+		if slot.load(Ordering::Relaxed) == 0 {
+			return None;
+		}
+
+		match slot.swap(0, Ordering::Relaxed) {
+			0 => None,
+			address => Some(address),
+		}
+	})
+}
+
+/// Stores a freed frame for reuse.
+///
+/// If all slots of [`FREE_FRAMES`] are taken, the frame is accounted as [`Stats::discarded`].
+fn push_free_frame(address: usize) {
+	let stored = FREE_FRAMES.iter().any(|slot| {
+		slot.compare_exchange(0, address, Ordering::Relaxed, Ordering::Relaxed)
+			.is_ok()
+	});
+	if !stored {
+		DISCARDED.fetch_add(BasePageSize::SIZE, Ordering::Relaxed);
+	}
+}
+
+/// Returns the number of frames in [`FREE_FRAMES`].
+fn free_frame_count() -> usize {
+	FREE_FRAMES
+		.iter()
+		.filter(|slot| slot.load(Ordering::Relaxed) != 0)
+		.count()
+}
+
+/// Allocates `size` bytes starting at a multiple of `align`.
+///
+/// Memory skipped for alignment is not reused and accounted as [`Stats::alignment_waste`].
+pub fn allocate_aligned(size: usize, align: usize) -> usize {
+	try_allocate_aligned(size, align).unwrap_or_else(|| {
+		panic!(
+			"Could not allocate {:#x} B of physical memory aligned to {:#x}: out of memory",
+			size, align
+		)
+	})
+}
+
+/// Allocates `size` bytes starting at a multiple of `align`, or returns None if there is not enough physical
+/// memory left.
+///
+/// `align` has to be a power of two and a multiple of the 4 KiB page size, e.g., `LargePageSize::SIZE` for
+/// backing a 2 MiB page.
+pub fn try_allocate_aligned(size: usize, align: usize) -> Option<usize> {
+	assert!(
+		align.is_power_of_two(),
+		"Alignment {:#x} is not a power of two",
+		align
+	);
+	assert_eq!(
+		align % BasePageSize::SIZE,
+		0,
+		"Alignment {:#x} is not a multiple of {:#x}",
+		align,
+		BasePageSize::SIZE
+	);
+
+	bump(size, align)
+}
+
+/// Allocates `count` physically contiguous frames of size `S` at once, e.g., for backing a range of huge pages.
+///
+/// The returned range starts at a multiple of `S::SIZE` and spans `count * S::SIZE` bytes.
+#[allow(dead_code)]
+pub fn allocate_contiguous<S: PageSize>(count: usize) -> Range<usize> {
+	let size = count.checked_mul(S::SIZE).unwrap_or_else(|| {
+		panic!(
+			"Could not allocate {} frames of {:#x} B: size overflows",
+			count,
+			S::SIZE
+		)
+	});
+	let start = try_allocate_aligned(size, S::SIZE).unwrap_or_else(|| {
+		panic!(
+			"Could not allocate {} contiguous frames of {:#x} B: out of memory",
+			count,
+			S::SIZE
+		)
+	});
+	start..start + size
+}
+
+/// Advances the allocation pointer by `size` bytes after aligning it to `align`.
+///
+/// Returns the start of the allocated memory, or None if there is not enough physical memory left.
+fn bump(size: usize, align: usize) -> Option<usize> {
 	assert!(size > 0);
 	assert_eq!(
 		size % BasePageSize::SIZE,
@@ -17,11 +287,168 @@ pub fn allocate(size: usize) -> usize {
 		size,
 		BasePageSize::SIZE
 	);
+	assert!(
+		INITIALIZED.load(Ordering::Acquire),
+		"Trying to allocate physical memory before the Physical Memory Manager has been initialized"
+	);
 
-	unsafe {
-		assert!(CURRENT_ADDRESS > 0, "Trying to allocate physical memory before the Physical Memory Manager has been initialized");
-		let address = CURRENT_ADDRESS;
-		CURRENT_ADDRESS += size;
-		address
+	let current_region = CURRENT_REGION.load(Ordering::Relaxed);
+	for (index, region) in regions().iter().enumerate().skip(current_region) {
+		let end_address = region.end.load(Ordering::Relaxed);
+		let previous =
+			region
+				.current
+				.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+					align_up!(current, align)
+						.checked_add(size)
+						.filter(|&end| end <= end_address)
+				});
+
+		if let Ok(previous) = previous {
+			CURRENT_REGION.fetch_max(index, Ordering::Relaxed);
+			let address = align_up!(previous, align);
+			ALIGNMENT_WASTE.fetch_add(address - previous, Ordering::Relaxed);
+			return Some(address);
+		}
+	}
+
+	None
+}
+
+/// Returns whether `size` more bytes could be allocated in single frames.
+pub fn can_allocate(size: usize) -> bool {
+	let size = size.saturating_sub(free_frame_count() * BasePageSize::SIZE);
+
+	let available: usize = regions()
+		.iter()
+		.skip(CURRENT_REGION.load(Ordering::Relaxed))
+		.map(|region| region.end.load(Ordering::Relaxed) - region.current.load(Ordering::Relaxed))
+		.sum();
+	size <= available
+}
+
+/// Returns memory to the Physical Memory Manager.
+///
+/// The most recent allocation of the current region is given back to the bump allocator.
+/// Any other memory is split into frames, which are reused by [`try_allocate`].
+/// The freed memory is not accessed, so it does not need to be mapped.
+///
+/// The memory must have been allocated from the Physical Memory Manager.
+pub fn deallocate(address: usize, size: usize) {
+	assert_eq!(
+		address % BasePageSize::SIZE,
+		0,
+		"Address {:#x} is not a multiple of {:#x}",
+		address,
+		BasePageSize::SIZE
+	);
+	assert_eq!(
+		size % BasePageSize::SIZE,
+		0,
+		"Size {:#x} is not a multiple of {:#x}",
+		size,
+		BasePageSize::SIZE
+	);
+	let region = region_of(address);
+	assert!(
+		region.is_some() && (size == 0 || region_of(address + size - 1) == region),
+		"Physical memory [{:#x} - {:#x}] has not been allocated from the Physical Memory Manager",
+		address,
+		address + size
+	);
+
+	let current_region = regions().get(CURRENT_REGION.load(Ordering::Relaxed));
+	if current_region.map_or(false, |region| {
+		region.contains(address)
+			&& region
+				.current
+				.compare_exchange(
+					address + size,
+					address,
+					Ordering::Relaxed,
+					Ordering::Relaxed,
+				)
+				.is_ok()
+	}) {
+		return;
+	}
+
+	for frame in (address..address + size).step_by(BasePageSize::SIZE) {
+		push_free_frame(frame);
+	}
+}
+
+/// Saves the current state of the Physical Memory Manager.
+///
+/// Everything allocated afterwards can be freed at once by passing the returned [`Watermark`] to [`release`].
+pub fn mark() -> Watermark {
+	let region = CURRENT_REGION.load(Ordering::Relaxed);
+	Watermark {
+		region,
+		current_address: regions()
+			.get(region)
+			.map_or(0, |region| region.current.load(Ordering::Relaxed)),
+		alignment_waste: ALIGNMENT_WASTE.load(Ordering::Relaxed),
+		discarded: DISCARDED.load(Ordering::Relaxed),
+	}
+}
+
+/// Frees everything that has been allocated since `watermark` was created.
+///
+/// None of this memory may still be in use or mapped afterwards.
+/// This includes memory allocated by other CPUs in the meantime.
+pub fn release(watermark: Watermark) {
+	let current_region = CURRENT_REGION.load(Ordering::Relaxed);
+	assert!(
+		watermark.region < current_region
+			|| watermark.region == current_region
+				&& regions().get(current_region).map_or(true, |region| {
+					watermark.current_address <= region.current.load(Ordering::Relaxed)
+				}),
+		"Trying to release memory that has already been released"
+	);
+
+	let regions = regions();
+	if let Some(region) = regions.get(watermark.region) {
+		region
+			.current
+			.store(watermark.current_address, Ordering::Relaxed);
+	}
+	for region in regions.iter().skip(watermark.region + 1) {
+		region
+			.current
+			.store(region.start.load(Ordering::Relaxed), Ordering::Relaxed);
+	}
+	CURRENT_REGION.store(watermark.region, Ordering::Relaxed);
+	ALIGNMENT_WASTE.store(watermark.alignment_waste, Ordering::Relaxed);
+	DISCARDED.store(watermark.discarded, Ordering::Relaxed);
+
+	// Freed frames above the watermark are handed out by the bump allocator again.
+	for slot in FREE_FRAMES.iter() {
+		let frame = slot.load(Ordering::Relaxed);
+		let above_watermark = region_of(frame).map_or(false, |region| {
+			region > watermark.region
+				|| region == watermark.region && frame >= watermark.current_address
+		});
+		if above_watermark {
+			let _ = slot.compare_exchange(frame, 0, Ordering::Relaxed, Ordering::Relaxed);
+		}
+	}
+}
+
+/// Returns the ranges of physical memory that have been handed out so far, one for each region in use.
+pub fn allocated_ranges() -> impl Iterator<Item = Range<usize>> {
+	regions()
+		.iter()
+		.take(CURRENT_REGION.load(Ordering::Relaxed) + 1)
+		.map(|region| region.start.load(Ordering::Relaxed)..region.current.load(Ordering::Relaxed))
+		.filter(|range| !range.is_empty())
+}
+
+pub fn stats() -> Stats {
+	Stats {
+		used: allocated_ranges().map(|range| range.len()).sum(),
+		alignment_waste: ALIGNMENT_WASTE.load(Ordering::Relaxed),
+		discarded: DISCARDED.load(Ordering::Relaxed),
 	}
 }
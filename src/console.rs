@@ -3,12 +3,20 @@ use core::fmt;
 pub struct Console(());
 
 impl fmt::Write for Console {
+	#[cfg(not(test))]
 	fn write_str(&mut self, s: &str) -> fmt::Result {
 		for byte in s.bytes() {
 			crate::arch::output_message_byte(byte);
 		}
 		Ok(())
 	}
+
+	/// Unit tests run on the host, where the loader's output device is not accessible.
+	#[cfg(test)]
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		std::print!("{s}");
+		Ok(())
+	}
 }
 
 pub static mut CONSOLE: Console = Console(());
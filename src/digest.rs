@@ -0,0 +1,165 @@
+//! Checksums for verifying the integrity of kernel images.
+#![deny(unsafe_code)]
+
+/// An expected checksum of a kernel image.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Digest {
+	/// CRC-32 as used by zlib and Ethernet (IEEE 802.3).
+	Crc32(u32),
+
+	/// SHA-256 hash.
+	#[cfg(feature = "sha256")]
+	Sha256([u8; 32]),
+}
+
+impl Digest {
+	/// Returns the checksum of the kernel image that has been configured when building the loader, if any.
+	///
+	/// The checksum is read from `HERMIT_APP_SHA256` (with the `sha256` feature) or `HERMIT_APP_CRC32`
+	/// as hexadecimal number.
+	pub fn expected() -> Option<Self> {
+		#[cfg(feature = "sha256")]
+		if let Some(sha256) = option_env!("HERMIT_APP_SHA256") {
+			assert_eq!(sha256.len(), 64, "HERMIT_APP_SHA256 is not 32 bytes long");
+			let mut digest = [0; 32];
+			for (i, byte) in digest.iter_mut().enumerate() {
+				*byte = u8::from_str_radix(&sha256[2 * i..][..2], 16)
+					.expect("HERMIT_APP_SHA256 is not a hexadecimal number");
+			}
+			return Some(Self::Sha256(digest));
+		}
+
+		option_env!("HERMIT_APP_CRC32").map(|crc32| {
+			Self::Crc32(
+				u32::from_str_radix(crc32, 16)
+					.expect("HERMIT_APP_CRC32 is not a hexadecimal number"),
+			)
+		})
+	}
+
+	/// Returns whether the checksum of `data` equals this one.
+	pub fn matches(&self, data: &[u8]) -> bool {
+		match self {
+			Self::Crc32(expected) => crc32(data) == *expected,
+			#[cfg(feature = "sha256")]
+			Self::Sha256(expected) => sha256(data) == *expected,
+		}
+	}
+}
+
+/// Computes the CRC-32 of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+	/// Reversed representation of the generator polynomial.
+	const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+	/// Lookup table with the remainders of all possible bytes.
+	const TABLE: [u32; 256] = {
+		let mut table = [0; 256];
+		let mut i = 0;
+		while i < table.len() {
+			let mut remainder = i as u32;
+			let mut bit = 0;
+			while bit < 8 {
+				remainder = if remainder & 1 != 0 {
+					(remainder >> 1) ^ POLYNOMIAL
+				} else {
+					remainder >> 1
+				};
+				bit += 1;
+			}
+			table[i] = remainder;
+			i += 1;
+		}
+		table
+	};
+
+	!data.iter().fold(!0, |crc, &byte| {
+		TABLE[usize::from((crc as u8) ^ byte)] ^ (crc >> 8)
+	})
+}
+
+/// Computes the SHA-256 hash of `data` (FIPS 180-4).
+#[cfg(feature = "sha256")]
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+	const K: [u32; 64] = [
+		0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+		0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+		0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+		0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+		0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+		0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+		0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+		0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+		0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+		0xc67178f2,
+	];
+
+	let mut state: [u32; 8] = [
+		0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+		0x5be0cd19,
+	];
+
+	let mut compress = |block: &[u8]| {
+		let mut w = [0; 64];
+		for (i, word) in block.chunks_exact(4).enumerate() {
+			w[i] = u32::from_be_bytes(word.try_into().unwrap());
+		}
+		for i in 16..64 {
+			let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+			let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+			w[i] = w[i - 16]
+				.wrapping_add(s0)
+				.wrapping_add(w[i - 7])
+				.wrapping_add(s1);
+		}
+
+		let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+		for i in 0..64 {
+			let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+			let ch = (e & f) ^ (!e & g);
+			let t1 = h
+				.wrapping_add(s1)
+				.wrapping_add(ch)
+				.wrapping_add(K[i])
+				.wrapping_add(w[i]);
+			let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+			let maj = (a & b) ^ (a & c) ^ (b & c);
+			let t2 = s0.wrapping_add(maj);
+
+			h = g;
+			g = f;
+			f = e;
+			e = d.wrapping_add(t1);
+			d = c;
+			c = b;
+			b = a;
+			a = t1.wrapping_add(t2);
+		}
+
+		for (value, new) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+			*value = value.wrapping_add(new);
+		}
+	};
+
+	let mut blocks = data.chunks_exact(64);
+	for block in &mut blocks {
+		compress(block);
+	}
+
+	// Pad the remaining data with a one bit, zeros, and the length in bits.
+	let remainder = blocks.remainder();
+	let mut padding = [0; 128];
+	padding[..remainder.len()].copy_from_slice(remainder);
+	padding[remainder.len()] = 0x80;
+	let padding_len = if remainder.len() < 56 { 64 } else { 128 };
+	padding[padding_len - 8..padding_len].copy_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+	for block in padding[..padding_len].chunks_exact(64) {
+		compress(block);
+	}
+
+	let mut digest = [0; 32];
+	for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+		bytes.copy_from_slice(&value.to_be_bytes());
+	}
+	digest
+}
@@ -1,18 +1,350 @@
 //! Parsing and loading kernel objects from ELF files.
 #![deny(unsafe_code)]
 
+use crate::arch::paging::{BasePageSize, MapError, PageSize};
 use crate::arch::{self, BootInfo};
+use crate::digest::Digest;
+use crate::lz4::{self, DecompressError};
 
+use core::fmt;
 use core::mem::{self, MaybeUninit};
+use core::ops::Range;
 
 use goblin::elf64::{
 	dynamic::{self, Dyn, DynamicInfo},
 	header::{self, Header},
 	program_header::{self, ProgramHeader},
-	reloc::{self, Rela},
+	reloc::{self, Rel, Rela},
+	section_header::{self, SectionHeader},
+	sym::{self, Sym},
 };
 use plain::Plain;
 
+/// Maximum number of loadable program segments of a kernel.
+const MAX_SEGMENTS: usize = 16;
+
+/// Size of the `DT_RELR` table in bytes.
+const DT_RELRSZ: u64 = 35;
+
+/// Address of the `DT_RELR` table of compact relative relocations.
+const DT_RELR: u64 = 36;
+
+/// Size of a `DT_RELR` table entry in bytes.
+const DT_RELRENT: u64 = 37;
+
+/// Value of `e_phnum` if the number of program headers is stored in `sh_info` of the first section header.
+const PN_XNUM: u16 = 0xffff;
+
+/// Note type of the GNU build ID, which uniquely identifies a build of the kernel.
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// Number of relocations between two progress messages while relocating the kernel.
+///
+/// Large kernels may take a noticeable amount of time to relocate.
+const RELOCATION_PROGRESS_INTERVAL: usize = 0x10_0000;
+
+/// An error that occurred while parsing a kernel object.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParseError {
+	/// The ELF file is cut off or refers to data beyond its end.
+	Truncated,
+
+	/// The kernel is not a 64-bit object.
+	NotElf64,
+
+	/// The kernel is not little endian.
+	WrongEndianness,
+
+	/// The kernel is neither an executable nor a position-independent executable.
+	UnsupportedType,
+
+	/// The kernel is not compiled for the correct architecture.
+	WrongArchitecture,
+
+	/// The kernel was linked against dynamic libraries.
+	LinkedAgainstSharedLibs,
+
+	/// The kernel has relocations of an unsupported type or format.
+	UnsupportedRelocation,
+
+	/// `DT_RELACOUNT` does not match the relocation table.
+	InconsistentRelocationCount,
+
+	/// The kernel has no loadable program segments.
+	NoLoadableSegments,
+
+	/// The kernel has more loadable program segments than supported.
+	TooManySegments,
+
+	/// A loadable program segment has inconsistent sizes or addresses, or an alignment that is not a power of two.
+	MalformedSegment,
+
+	/// Two loadable program segments occupy the same memory.
+	OverlappingSegments,
+
+	/// A relocation targets memory outside of the loadable program segments.
+	RelocationOutOfBounds,
+
+	/// The compressed kernel could not be decompressed.
+	Decompression(DecompressError),
+
+	/// No memory is available for decompressing the kernel.
+	NoScratchMemory,
+
+	/// The checksum of the kernel does not match the expected one.
+	IntegrityMismatch,
+
+	/// An address, offset, or size does not fit into the address space of the loader.
+	AddressTruncated,
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Truncated => f.write_str("ELF file is truncated"),
+			Self::NotElf64 => f.write_str("kernel is not a 64-bit ELF file"),
+			Self::WrongEndianness => f.write_str("kernel is not little endian"),
+			Self::UnsupportedType => f.write_str("kernel is neither ET_EXEC nor ET_DYN"),
+			Self::WrongArchitecture => {
+				f.write_str("kernel is compiled for a different architecture")
+			}
+			Self::LinkedAgainstSharedLibs => {
+				f.write_str("kernel is linked against shared libraries, link it statically")
+			}
+			Self::UnsupportedRelocation => f.write_str("kernel has unsupported relocations"),
+			Self::InconsistentRelocationCount => {
+				f.write_str("DT_RELACOUNT does not match the relocation table")
+			}
+			Self::NoLoadableSegments => f.write_str("kernel has no loadable program segments"),
+			Self::TooManySegments => write!(
+				f,
+				"kernel has more than {MAX_SEGMENTS} loadable program segments"
+			),
+			Self::MalformedSegment => {
+				f.write_str("kernel has a malformed loadable program segment")
+			}
+			Self::OverlappingSegments => f.write_str("kernel has overlapping program segments"),
+			Self::RelocationOutOfBounds => {
+				f.write_str("kernel has a relocation outside of its segments")
+			}
+			Self::Decompression(err) => write!(f, "kernel could not be decompressed ({err:?})"),
+			Self::NoScratchMemory => {
+				f.write_str("no memory is available for decompressing the kernel")
+			}
+			Self::IntegrityMismatch => f.write_str("kernel checksum does not match"),
+			Self::AddressTruncated => {
+				f.write_str("kernel does not fit into the address space of the loader")
+			}
+		}
+	}
+}
+
+/// An error that occurred while loading a kernel object, including parsing it and mapping it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadError {
+	/// The kernel could not be parsed.
+	Parse(ParseError),
+
+	/// The kernel could not be mapped.
+	Map(MapError),
+
+	/// The relocated entry point is not aligned to an instruction boundary.
+	MisalignedEntryPoint(u64),
+
+	/// The relocated entry point does not lie within an executable segment.
+	EntryPointNotExecutable(u64),
+
+	/// A relocation at the given virtual address targets memory outside of the loaded kernel.
+	RelocationOutOfBounds(u64),
+
+	/// The relocated value of the relocation at the given virtual address does not fit into 64 bits.
+	RelocationOverflow(u64),
+
+	/// The indirect relocation at the given virtual address of an `ET_EXEC` kernel cannot be resolved, since the
+	/// kernel is not loaded to its fixed address.
+	IndirectRelocationNotAtFixedAddress(u64),
+
+	/// The kernel is position-independent and has no fixed address.
+	NoFixedAddress,
+
+	/// The memory for the `ET_EXEC` kernel is not located at the kernel's fixed address, which is given.
+	NotAtFixedAddress(u64),
+}
+
+impl From<ParseError> for LoadError {
+	fn from(err: ParseError) -> Self {
+		Self::Parse(err)
+	}
+}
+
+impl From<MapError> for LoadError {
+	fn from(err: MapError) -> Self {
+		Self::Map(err)
+	}
+}
+
+impl fmt::Display for LoadError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Parse(err) => write!(f, "{err}"),
+			Self::Map(err) => write!(f, "kernel could not be mapped ({err:?})"),
+			Self::MisalignedEntryPoint(entry_point) => write!(
+				f,
+				"kernel entry point {entry_point:#x} is not aligned to an instruction boundary"
+			),
+			Self::EntryPointNotExecutable(entry_point) => write!(
+				f,
+				"kernel entry point {entry_point:#x} is not within an executable segment"
+			),
+			Self::RelocationOutOfBounds(offset) => write!(
+				f,
+				"relocation at {offset:#x} lies outside of the kernel memory"
+			),
+			Self::RelocationOverflow(offset) => {
+				write!(f, "relocation at {offset:#x} overflows")
+			}
+			Self::IndirectRelocationNotAtFixedAddress(offset) => write!(
+				f,
+				"indirect relocation at {offset:#x} requires the kernel to be loaded to its fixed address"
+			),
+			Self::NoFixedAddress => f.write_str("kernel is position-independent"),
+			Self::NotAtFixedAddress(address) => write!(
+				f,
+				"kernel memory is not located at the kernel's fixed address {address:#x}"
+			),
+		}
+	}
+}
+
+/// Converts an address, offset, or size from the ELF file to `usize`.
+///
+/// Fails if the value does not fit, e.g., when the loader is built for a 32-bit target.
+fn to_usize(value: u64) -> Result<usize, ParseError> {
+	value.try_into().map_err(|_| ParseError::AddressTruncated)
+}
+
+/// Returns `len` values of type `T` from `elf` starting at offset `start`.
+fn slice_at<T: Plain>(elf: &[u8], start: usize, len: usize) -> Result<&[T], ParseError> {
+	let bytes = elf.get(start..).ok_or(ParseError::Truncated)?;
+	T::slice_from_bytes_len(bytes, len).map_err(|_| ParseError::Truncated)
+}
+
+/// Returns the descriptor of the GNU build ID note in the given note segment, if any.
+///
+/// Each note consists of a header of three 4-byte words (name size, descriptor size and type), followed by the
+/// name and the descriptor, which are both padded to 4 bytes.
+fn find_build_id(notes: &[u8]) -> Result<Option<&[u8]>, ParseError> {
+	let word = |bytes: &[u8], index: usize| -> Result<u32, ParseError> {
+		let word = bytes
+			.get(index * 4..index * 4 + 4)
+			.ok_or(ParseError::Truncated)?;
+		Ok(u32::from_ne_bytes(word.try_into().unwrap()))
+	};
+
+	let mut notes = notes;
+	while !notes.is_empty() {
+		let name_size = word(notes, 0)? as usize;
+		let desc_size = word(notes, 1)? as usize;
+		let note_type = word(notes, 2)?;
+
+		let name_start = 3 * 4;
+		let desc_start = name_start + align_up!(name_size, 4);
+		let next_start = desc_start + align_up!(desc_size, 4);
+		if next_start > notes.len() {
+			return Err(ParseError::Truncated);
+		}
+
+		let name = &notes[name_start..][..name_size];
+		if note_type == NT_GNU_BUILD_ID && name == b"GNU\0" {
+			return Ok(Some(&notes[desc_start..][..desc_size]));
+		}
+
+		notes = &notes[next_start..];
+	}
+
+	Ok(None)
+}
+
+/// Returns the offsets of all compact relative relocations (`DT_RELR`).
+///
+/// An even entry is the address of a relocation and marks the start of the following bitmaps.
+/// An odd entry is a bitmap of the following 63 words, of which those with a set bit are relocated.
+fn relr_offsets(relrs: &[u64]) -> impl Iterator<Item = u64> + '_ {
+	const WORD_SIZE: u64 = mem::size_of::<u64>() as u64;
+
+	let mut base = 0;
+	relrs.iter().flat_map(move |&entry| {
+		let (first, bitmap) = if entry & 1 == 0 {
+			// Encode the address as a bitmap with only the first bit set.
+			(entry, 1)
+		} else {
+			(base, entry >> 1)
+		};
+		base = if entry & 1 == 0 {
+			entry + WORD_SIZE
+		} else {
+			base + 63 * WORD_SIZE
+		};
+
+		(0..64)
+			.filter(move |bit| bitmap & (1 << bit) != 0)
+			.map(move |bit| first + bit * WORD_SIZE)
+	})
+}
+
+/// Converts a virtual address into an offset into the ELF file using the loadable program segments.
+fn file_offset(phs: &[ProgramHeader], address: u64) -> Option<usize> {
+	phs.iter()
+		.filter(|ph| ph.p_type == program_header::PT_LOAD)
+		.find(|ph| (ph.p_vaddr..ph.p_vaddr + ph.p_filesz).contains(&address))
+		.and_then(|ph| to_usize(address - ph.p_vaddr + ph.p_offset).ok())
+}
+
+/// Returns the NUL-terminated string at `offset` in the string table `strtab`, or None if it is out of bounds.
+fn string_at(strtab: &[u8], offset: u32) -> Option<&[u8]> {
+	strtab
+		.get(offset as usize..)?
+		.split(|&byte| byte == 0)
+		.next()
+}
+
+/// Returns whether relocations of the given type can be applied.
+fn is_supported_relocation(r_type: u32) -> bool {
+	matches!(
+		r_type,
+		arch::R_RELATIVE | arch::R_ABS64 | arch::R_GLOB_DAT | arch::R_JUMP_SLOT | arch::R_IRELATIVE
+	)
+}
+
+/// Writes the relocated value `bytes` to the virtual address `offset` of the kernel, whose memory starts at the
+/// virtual address `start_addr`.
+///
+/// Fails if the value does not lie completely within `memory`.
+fn write_relocated(
+	memory: &mut [MaybeUninit<u8>],
+	start_addr: u64,
+	offset: u64,
+	bytes: [u8; 8],
+) -> Result<(), LoadError> {
+	let target = offset
+		.checked_sub(start_addr)
+		.and_then(|offset| usize::try_from(offset).ok())
+		.and_then(|offset| memory.get_mut(offset..)?.get_mut(..bytes.len()))
+		.ok_or(LoadError::RelocationOutOfBounds(offset))?;
+	MaybeUninit::write_slice(target, &bytes);
+	Ok(())
+}
+
+/// Calls the indirect function resolver at `resolver` and returns the address of the selected implementation.
+///
+/// The resolver must be part of a fully relocated kernel in executable memory.
+#[allow(unsafe_code)]
+fn call_resolver(resolver: u64) -> u64 {
+	// SAFETY: The resolver is a function of the relocated kernel without arguments.
+	let resolver: extern "C" fn() -> u64 = unsafe { mem::transmute(resolver as usize) };
+	resolver()
+}
+
 /// A parsed kernel object ready for loading.
 pub struct Object<'a> {
 	/// The raw bytes of the parsed ELF file.
@@ -28,184 +360,1125 @@ pub struct Object<'a> {
 	/// The thread-local storage segment will be used for creating [`TlsInfo`] for the kernel.
 	phs: &'a [ProgramHeader],
 
+	/// The kernel's section headers.
+	///
+	/// These are only used for diagnostics and may be empty.
+	shs: &'a [SectionHeader],
+
 	/// Relocations with an explicit addend.
 	relas: &'a [Rela],
+
+	/// Relocations with an implicit addend, which is stored at the relocation target.
+	rels: &'a [Rel],
+
+	/// Compact relative relocations (`DT_RELR`), which are a bitmap-encoded list of addresses.
+	relrs: &'a [u64],
+
+	/// The dynamic symbol table (`DT_SYMTAB`) referenced by [`Self::relas`] and [`Self::rels`].
+	dynsyms: &'a [Sym],
+
+	/// The string table of [`Self::dynsyms`] (`DT_STRTAB`).
+	dynstr: &'a [u8],
+
+	/// The GNU build ID of the kernel (`NT_GNU_BUILD_ID`).
+	build_id: Option<&'a [u8]>,
+
+	/// Whether all relocations have to be processed before running the kernel (`DF_BIND_NOW`).
+	bind_now: bool,
 }
 
 impl<'a> Object<'a> {
+	/// Verifies the checksum of the raw bytes of an ELF file before parsing them into a loadable kernel object.
+	///
+	/// The checksum covers the raw bytes, which may be compressed (see [`Self::parse_compressed`]).
+	pub fn parse_verified(
+		input: &'a [u8],
+		expected: &Digest,
+		scratch: impl FnOnce(usize) -> Option<&'a mut [MaybeUninit<u8>]>,
+	) -> Result<Object<'a>, ParseError> {
+		if !expected.matches(input) {
+			return Err(ParseError::IntegrityMismatch);
+		}
+
+		loaderlog!("Verified kernel checksum: {expected:x?}");
+		Object::parse_compressed(input, scratch)
+	}
+
+	/// Parses raw bytes of an ELF file, which may be compressed as an LZ4 frame, into a loadable kernel object.
+	///
+	/// For decompressing, `scratch` is called with the decompressed size of the kernel
+	/// and has to return memory of at least that size, or None if no memory for decompressing is available.
+	pub fn parse_compressed(
+		input: &'a [u8],
+		scratch: impl FnOnce(usize) -> Option<&'a mut [MaybeUninit<u8>]>,
+	) -> Result<Object<'a>, ParseError> {
+		if !lz4::is_compressed(input) {
+			return Object::parse(input);
+		}
+
+		let size = lz4::decompressed_size(input).map_err(ParseError::Decompression)?;
+		loaderlog!("Decompressing kernel ({} B to {size} B)", input.len());
+		let scratch = scratch(size).ok_or(ParseError::NoScratchMemory)?;
+		let elf = lz4::decompress(input, scratch).map_err(ParseError::Decompression)?;
+		Object::parse(elf)
+	}
+
 	/// Parses raw bytes of an ELF file into a loadable kernel object.
-	pub fn parse(elf: &[u8]) -> Object<'_> {
+	pub fn parse(elf: &[u8]) -> Result<Object<'_>, ParseError> {
 		{
 			let range = elf.as_ptr_range();
 			let len = elf.len();
 			loaderlog!("Parsing kernel from ELF at {range:?} ({len} B)");
 		}
 
-		let header = plain::from_bytes::<Header>(elf).unwrap();
+		let header = plain::from_bytes::<Header>(elf).map_err(|_| ParseError::Truncated)?;
 
 		// General compatibility checks
 		{
 			let class = header.e_ident[header::EI_CLASS];
-			assert_eq!(header::ELFCLASS64, class, "kernel ist not a 64-bit object");
+			if class != header::ELFCLASS64 {
+				return Err(ParseError::NotElf64);
+			}
+
 			let data_encoding = header.e_ident[header::EI_DATA];
-			assert_eq!(
-				header::ELFDATA2LSB,
-				data_encoding,
-				"kernel object is not little endian"
-			);
+			if data_encoding != header::ELFDATA2LSB {
+				return Err(ParseError::WrongEndianness);
+			}
 
-			assert!(
-				matches!(header.e_type, header::ET_DYN | header::ET_EXEC),
-				"kernel has unsupported ELF type"
-			);
+			if !matches!(header.e_type, header::ET_DYN | header::ET_EXEC) {
+				return Err(ParseError::UnsupportedType);
+			}
 
-			assert_eq!(
-				arch::ELF_ARCH,
-				header.e_machine,
-				"kernel is not compiled for the correct architecture"
-			);
+			if header.e_machine != arch::ELF_ARCH {
+				return Err(ParseError::WrongArchitecture);
+			}
 		}
 
 		let phs = {
-			let start = header.e_phoff as usize;
-			let len = header.e_phnum as usize;
-			ProgramHeader::slice_from_bytes_len(&elf[start..], len).unwrap()
+			let start = to_usize(header.e_phoff)?;
+			let len = if header.e_phnum == PN_XNUM {
+				// The section header table is required for finding the program headers.
+				if header.e_shoff == 0 {
+					return Err(ParseError::Truncated);
+				}
+				let first_sh = slice_at::<SectionHeader>(elf, to_usize(header.e_shoff)?, 1)?
+					.first()
+					.ok_or(ParseError::Truncated)?;
+				usize::try_from(first_sh.sh_info).map_err(|_| ParseError::AddressTruncated)?
+			} else {
+				usize::from(header.e_phnum)
+			};
+			slice_at::<ProgramHeader>(elf, start, len)?
 		};
 
-		let dyns = phs
+		let segment_count = phs
+			.iter()
+			.filter(|ph| ph.p_type == program_header::PT_LOAD)
+			.count();
+		if segment_count == 0 {
+			return Err(ParseError::NoLoadableSegments);
+		}
+		if segment_count > MAX_SEGMENTS {
+			return Err(ParseError::TooManySegments);
+		}
+
+		// Make sure all loadable segments can be copied into the memory allocated for the kernel.
+		let mut load_phs = phs.iter().filter(|ph| ph.p_type == program_header::PT_LOAD);
+		let load_start_addr = load_phs.clone().next().map_or(0, |ph| ph.p_vaddr);
+		let load_end_addr = load_phs
+			.clone()
+			.last()
+			.map_or(0, |ph| ph.p_vaddr.wrapping_add(ph.p_memsz));
+		if load_phs.any(|ph| {
+			ph.p_offset
+				.checked_add(ph.p_filesz)
+				.map_or(true, |end| end > elf.len() as u64)
+		}) {
+			return Err(ParseError::Truncated);
+		}
+		if phs
+			.iter()
+			.filter(|ph| ph.p_type == program_header::PT_LOAD)
+			.any(|ph| {
+				ph.p_filesz > ph.p_memsz
+					// Alignments of zero and one both mean that the segment is not aligned.
+					|| (ph.p_align > 1 && !ph.p_align.is_power_of_two())
+					|| ph.p_vaddr < load_start_addr
+					|| ph
+						.p_vaddr
+						.checked_add(ph.p_memsz)
+						.map_or(true, |end| end > load_end_addr)
+			}) {
+			return Err(ParseError::MalformedSegment);
+		}
+
+		// The memory the kernel is loaded to has to be addressable by the loader.
+		to_usize(load_end_addr - load_start_addr)?;
+
+		// Segments are copied into the same memory, so they must not overlap.
+		{
+			let mut ranges = [(0, 0); MAX_SEGMENTS];
+			let mut range_count = 0;
+			for ph in phs
+				.iter()
+				.filter(|ph| ph.p_type == program_header::PT_LOAD && ph.p_memsz > 0)
+			{
+				ranges[range_count] = (ph.p_vaddr, ph.p_vaddr + ph.p_memsz);
+				range_count += 1;
+			}
+
+			let ranges = &mut ranges[..range_count];
+			ranges.sort_unstable();
+			if ranges.windows(2).any(|pair| pair[0].1 > pair[1].0) {
+				return Err(ParseError::OverlappingSegments);
+			}
+		}
+
+		let shs = {
+			let start = to_usize(header.e_shoff).unwrap_or(usize::MAX);
+			let len = usize::from(header.e_shnum);
+			elf.get(start..)
+				.and_then(|bytes| SectionHeader::slice_from_bytes_len(bytes, len).ok())
+				.unwrap_or_default()
+		};
+
+		let dyns = match phs
 			.iter()
 			.find(|program_header| program_header.p_type == program_header::PT_DYNAMIC)
-			.map(|ph| {
-				let start = ph.p_offset as usize;
-				let len = (ph.p_filesz as usize) / dynamic::SIZEOF_DYN;
-				Dyn::slice_from_bytes_len(&elf[start..], len).unwrap()
-			})
-			.unwrap_or_default();
+		{
+			Some(ph) => {
+				let start = to_usize(ph.p_offset)?;
+				let len = to_usize(ph.p_filesz)? / dynamic::SIZEOF_DYN;
+				slice_at::<Dyn>(elf, start, len)?
+			}
+			None => &[],
+		};
 
-		assert!(
-			!dyns.iter().any(|d| d.d_tag == dynamic::DT_NEEDED),
-			"kernel was linked against dynamic libraries"
-		);
+		if dyns.iter().any(|d| d.d_tag == dynamic::DT_NEEDED) {
+			return Err(ParseError::LinkedAgainstSharedLibs);
+		}
 
 		let dynamic_info = DynamicInfo::new(dyns, phs);
-		assert_eq!(0, dynamic_info.relcount);
+
+		let bind_now = dyns.iter().any(|d| match d.d_tag {
+			dynamic::DT_BIND_NOW => true,
+			dynamic::DT_FLAGS => d.d_val & dynamic::DF_BIND_NOW != 0,
+			dynamic::DT_FLAGS_1 => d.d_val & dynamic::DF_1_NOW != 0,
+			_ => false,
+		});
+
+		let rela_total = if dynamic_info.relasz > 0 {
+			if dynamic_info.relaent != reloc::SIZEOF_RELA as u64 {
+				return Err(ParseError::UnsupportedRelocation);
+			}
+			dynamic_info.relasz / reloc::SIZEOF_RELA
+		} else {
+			0
+		};
+
+		// DT_RELACOUNT counts the relative relocations, which come first in the relocation table.
+		if dynamic_info.relacount > rela_total {
+			return Err(ParseError::InconsistentRelocationCount);
+		}
 
 		let relas = {
 			let start = dynamic_info.rela;
-			let len = dynamic_info.relacount;
-			Rela::slice_from_bytes_len(&elf[start..], len).unwrap()
+			let len = rela_total;
+			slice_at::<Rela>(elf, start, len)?
 		};
 
-		assert!(relas
+		if !relas[..dynamic_info.relacount]
+			.iter()
+			.all(|rela| reloc::r_type(rela.r_info) == arch::R_RELATIVE)
+		{
+			return Err(ParseError::InconsistentRelocationCount);
+		}
+
+		if !relas
 			.iter()
-			.all(|rela| reloc::r_type(rela.r_info) == arch::R_RELATIVE));
+			.all(|rela| is_supported_relocation(reloc::r_type(rela.r_info)))
+		{
+			return Err(ParseError::UnsupportedRelocation);
+		}
 
-		Object {
+		let rel_total = if dynamic_info.relsz > 0 {
+			if dynamic_info.relent != reloc::SIZEOF_REL as u64 {
+				return Err(ParseError::UnsupportedRelocation);
+			}
+			dynamic_info.relsz / reloc::SIZEOF_REL
+		} else {
+			0
+		};
+
+		// DT_RELCOUNT counts the relative relocations, which come first in the relocation table.
+		if dynamic_info.relcount > rel_total {
+			return Err(ParseError::InconsistentRelocationCount);
+		}
+
+		let rels = {
+			let start = dynamic_info.rel;
+			let len = rel_total;
+			slice_at::<Rel>(elf, start, len)?
+		};
+
+		if !rels[..dynamic_info.relcount]
+			.iter()
+			.all(|rel| reloc::r_type(rel.r_info) == arch::R_RELATIVE)
+		{
+			return Err(ParseError::InconsistentRelocationCount);
+		}
+
+		if !rels
+			.iter()
+			.all(|rel| is_supported_relocation(reloc::r_type(rel.r_info)))
+		{
+			return Err(ParseError::UnsupportedRelocation);
+		}
+
+		let relrs = {
+			let dyn_value = |tag| dyns.iter().find(|d| d.d_tag == tag).map(|d| d.d_val);
+			match dyn_value(DT_RELR) {
+				Some(relr) => {
+					let relrsz = dyn_value(DT_RELRSZ).unwrap_or(0);
+					let relrent = dyn_value(DT_RELRENT).unwrap_or(mem::size_of::<u64>() as u64);
+					if relrent != mem::size_of::<u64>() as u64 {
+						return Err(ParseError::UnsupportedRelocation);
+					}
+
+					let start = file_offset(phs, relr).ok_or(ParseError::Truncated)?;
+					let len = to_usize(relrsz / relrent)?;
+					slice_at::<u64>(elf, start, len)?
+				}
+				None => &[],
+			}
+		};
+
+		// The number of dynamic symbols is the number of chains in the ELF hash table (DT_HASH).
+		// Without one, the symbol table is assumed to end where the string table begins, as laid out by linkers.
+		let dynsyms = if dynamic_info.symtab == 0 {
+			&[]
+		} else {
+			let start = dynamic_info.symtab;
+			let len = match dynamic_info.hash.and_then(|hash| file_offset(phs, hash)) {
+				Some(hash) => slice_at::<u32>(elf, hash, 2)?[1] as usize,
+				None => dynamic_info.strtab.saturating_sub(dynamic_info.symtab) / sym::SIZEOF_SYM,
+			};
+			slice_at::<Sym>(elf, start, len)?
+		};
+
+		let dynstr = elf
+			.get(dynamic_info.strtab..)
+			.and_then(|bytes| bytes.get(..dynamic_info.strsz))
+			.ok_or(ParseError::Truncated)?;
+
+		// Symbol relocations must refer to defined dynamic symbols or to undefined weak ones, which resolve to zero.
+		let resolvable = |info: u64| match reloc::r_type(info) {
+			arch::R_ABS64 | arch::R_GLOB_DAT | arch::R_JUMP_SLOT => dynsyms
+				.get(reloc::r_sym(info) as usize)
+				.map_or(false, |symbol| {
+					u32::from(symbol.st_shndx) != section_header::SHN_UNDEF
+						|| sym::st_bind(symbol.st_info) == sym::STB_WEAK
+				}),
+			_ => true,
+		};
+		if !(relas.iter().all(|rela| resolvable(rela.r_info))
+			&& rels.iter().all(|rel| resolvable(rel.r_info)))
+		{
+			return Err(ParseError::UnsupportedRelocation);
+		}
+
+		let mut build_id = None;
+		for ph in phs.iter().filter(|ph| ph.p_type == program_header::PT_NOTE) {
+			let notes = elf
+				.get(to_usize(ph.p_offset)?..)
+				.and_then(|bytes| bytes.get(..to_usize(ph.p_filesz).ok()?))
+				.ok_or(ParseError::Truncated)?;
+			build_id = find_build_id(notes)?;
+			if build_id.is_some() {
+				break;
+			}
+		}
+
+		// Relocations are applied to the loaded kernel, which is indexed by virtual address.
+		// Each target has to lie within a single segment, since the gaps between segments are not initialized.
+		let in_bounds = |offset: u64| {
+			offset
+				.checked_add(mem::size_of::<u64>() as u64)
+				.map_or(false, |end| {
+					phs.iter()
+						.filter(|ph| ph.p_type == program_header::PT_LOAD)
+						.any(|ph| offset >= ph.p_vaddr && end <= ph.p_vaddr + ph.p_memsz)
+				})
+		};
+		if !(relas.iter().all(|rela| in_bounds(rela.r_offset))
+			&& rels.iter().all(|rel| in_bounds(rel.r_offset))
+			&& relr_offsets(relrs).all(in_bounds))
+		{
+			return Err(ParseError::RelocationOutOfBounds);
+		}
+
+		Ok(Object {
 			elf,
 			header,
 			phs,
+			shs,
 			relas,
+			rels,
+			relrs,
+			dynsyms,
+			dynstr,
+			build_id,
+			bind_now,
+		})
+	}
+
+	/// Returns whether the kernel's stack has to be executable.
+	///
+	/// This is requested by the `PF_X` flag of the `PT_GNU_STACK` program header.
+	/// Without that header, the stack is not executable.
+	pub fn stack_executable(&self) -> bool {
+		self.phs
+			.iter()
+			.find(|ph| ph.p_type == program_header::PT_GNU_STACK)
+			.map_or(false, |ph| ph.p_flags & program_header::PF_X != 0)
+	}
+
+	/// Returns which hardening measures the kernel has been built with.
+	pub fn security_summary(&self) -> SecuritySummary {
+		SecuritySummary {
+			pie: self.header.e_type == header::ET_DYN,
+			relro: self
+				.phs
+				.iter()
+				.any(|ph| ph.p_type == program_header::PT_GNU_RELRO),
+			bind_now: self.bind_now,
+			nx_stack: !self.stack_executable(),
 		}
 	}
 
+	/// Returns the GNU build ID of the kernel, if it has one.
+	pub fn build_id(&self) -> Option<&[u8]> {
+		self.build_id
+	}
+
 	/// Required memory size for loading.
 	///
 	/// Returns the minimum size of a block of memory for successfully loading the object.
+	/// This includes the padding in front of the first segment for aligning the memory to [`Self::required_align`].
 	pub fn mem_size(&self) -> usize {
-		let first_ph = self
-			.phs
-			.iter()
-			.find(|ph| ph.p_type == program_header::PT_LOAD)
-			.unwrap();
-		let start_addr = first_ph.p_vaddr;
+		let start_addr = self.load_start_addr();
 
 		let last_ph = self
 			.phs
 			.iter()
 			.rev()
 			.find(|ph| ph.p_type == program_header::PT_LOAD)
-			.unwrap();
+			.expect("kernel has no loadable segments");
 		let end_addr = last_ph.p_vaddr + last_ph.p_memsz;
 
 		let mem_size = end_addr - start_addr;
 		mem_size.try_into().unwrap()
 	}
 
-	/// Loads the kernel into the provided memory.
-	pub fn load_kernel(&self, memory: &mut [MaybeUninit<u8>]) -> LoadInfo {
-		loaderlog!("Loading kernel to {memory:p}");
+	/// Returns how much of the loaded kernel is copied from the ELF file and how much is zero-initialized.
+	pub fn size_breakdown(&self) -> SizeBreakdown {
+		let load_phs = || {
+			self.phs
+				.iter()
+				.filter(|ph| ph.p_type == program_header::PT_LOAD)
+		};
 
-		assert!(memory.len() >= self.mem_size());
+		SizeBreakdown {
+			file_size: load_phs().map(|ph| ph.p_filesz as usize).sum(),
+			bss_size: load_phs()
+				.map(|ph| (ph.p_memsz - ph.p_filesz) as usize)
+				.sum(),
+			total: self.mem_size(),
+		}
+	}
 
-		let load_start_addr = self
+	/// Returns the virtual address the memory for loading the kernel corresponds to.
+	///
+	/// This is the start of the first loadable program segment, aligned down to [`Self::required_align`].
+	/// Thus, each segment keeps its alignment when placed relative to an aligned block of memory.
+	fn load_start_addr(&self) -> u64 {
+		let first_ph = self
 			.phs
 			.iter()
 			.find(|ph| ph.p_type == program_header::PT_LOAD)
-			.unwrap()
-			.p_vaddr;
+			.expect("kernel has no loadable segments");
+		align_down!(first_ph.p_vaddr, self.required_align())
+	}
 
-		// Load program segments
-		// Contains TLS initialization image
+	/// Required memory alignment for loading.
+	///
+	/// Returns the largest alignment requested by any loadable program segment.
+	/// Alignments have been checked to be powers of two when parsing.
+	pub fn required_align(&self) -> u64 {
 		self.phs
 			.iter()
 			.filter(|ph| ph.p_type == program_header::PT_LOAD)
-			.for_each(|ph| {
-				let ph_memory = {
-					let mem_start = (ph.p_vaddr - load_start_addr) as usize;
-					let mem_len = ph.p_memsz as usize;
-					&mut memory[mem_start..][..mem_len]
-				};
-				let file_len = ph.p_filesz as usize;
-				let ph_file = &self.elf[ph.p_offset as usize..][..file_len];
-				MaybeUninit::write_slice(&mut ph_memory[..file_len], ph_file);
-				for byte in &mut ph_memory[file_len..] {
-					byte.write(0);
-				}
-			});
+			.map(|ph| ph.p_align)
+			.max()
+			.unwrap_or(1)
+			.max(1)
+	}
 
-		// Perform relocations
-		self.relas.iter().for_each(|rela| {
-			let kernel_addr = memory.as_ptr() as i64;
-			match reloc::r_type(rela.r_info) {
-				arch::R_RELATIVE => {
-					let relocated = kernel_addr + rela.r_addend;
-					MaybeUninit::write_slice(
-						&mut memory[rela.r_offset as usize..][..mem::size_of_val(&relocated)],
-						&relocated.to_ne_bytes(),
-					);
-				}
-				_ => unreachable!(),
-			}
-		});
+	/// Returns whether the dynamic symbol table or any symbol table section of the kernel contains a symbol with
+	/// the given name.
+	///
+	/// Malformed symbol table sections are ignored.
+	fn has_symbol(&self, name: &str) -> bool {
+		let contains = |symbols: &[Sym], strtab: &[u8]| {
+			symbols
+				.iter()
+				.any(|symbol| string_at(strtab, symbol.st_name) == Some(name.as_bytes()))
+		};
+
+		contains(self.dynsyms, self.dynstr)
+			|| self
+				.shs
+				.iter()
+				.filter(|sh| {
+					matches!(
+						sh.sh_type,
+						section_header::SHT_SYMTAB | section_header::SHT_DYNSYM
+					)
+				})
+				.any(|sh| {
+					let section = |sh: &SectionHeader| {
+						let start = to_usize(sh.sh_offset).ok()?;
+						let len = to_usize(sh.sh_size).ok()?;
+						self.elf.get(start..)?.get(..len)
+					};
+					let strtab = self.shs.get(sh.sh_link as usize).and_then(section);
+					let symbols = section(sh).and_then(|bytes| {
+						Sym::slice_from_bytes_len(bytes, bytes.len() / sym::SIZEOF_SYM).ok()
+					});
+					match (symbols, strtab) {
+						(Some(symbols), Some(strtab)) => contains(symbols, strtab),
+						_ => false,
+					}
+				})
+	}
+
+	/// Returns the address of the defined dynamic symbol with the given name when loading the kernel to `load_base`.
+	///
+	/// Position-independent kernels (`ET_DYN`) are shifted from their preferred base to `load_base`.
+	pub fn symbol(&self, name: &str, load_base: u64) -> Option<u64> {
+		let symbol = self
+			.dynsyms
+			.iter()
+			.filter(|symbol| u32::from(symbol.st_shndx) != section_header::SHN_UNDEF)
+			.find(|symbol| string_at(self.dynstr, symbol.st_name) == Some(name.as_bytes()))?;
+
+		Some(symbol.st_value.wrapping_add(self.base(load_base)))
+	}
+
+	/// Returns the relocated value of the dynamic symbol with the given index for the relocation at `offset`.
+	///
+	/// Undefined weak symbols resolve to zero.
+	/// The symbol has been checked to exist and to be defined or weak when parsing.
+	fn dynamic_symbol_value(
+		&self,
+		index: u32,
+		kernel_addr: i64,
+		offset: u64,
+	) -> Result<i64, LoadError> {
+		let symbol = &self.dynsyms[index as usize];
+		if u32::from(symbol.st_shndx) == section_header::SHN_UNDEF {
+			Ok(0)
+		} else {
+			kernel_addr
+				.checked_add(symbol.st_value as i64)
+				.ok_or(LoadError::RelocationOverflow(offset))
+		}
+	}
+
+	/// Returns the virtual address of the first indirect relocation (`IRELATIVE`), if any.
+	fn indirect_relocation(&self) -> Option<u64> {
+		self.relas
+			.iter()
+			.map(|rela| (rela.r_offset, rela.r_info))
+			.chain(self.rels.iter().map(|rel| (rel.r_offset, rel.r_info)))
+			.find(|&(_, info)| reloc::r_type(info) == arch::R_IRELATIVE)
+			.map(|(offset, _)| offset)
+	}
+
+	/// Returns the implicit addend of a relocation without explicit addend.
+	///
+	/// The addend is stored at the relocation target, so it is read from the loaded and transformed `memory`
+	/// before relocating.
+	#[allow(unsafe_code)]
+	fn implicit_addend(&self, memory: &[MaybeUninit<u8>], offset: u64) -> Result<i64, LoadError> {
+		let ph = self
+			.phs
+			.iter()
+			.filter(|ph| ph.p_type == program_header::PT_LOAD)
+			.find(|ph| (ph.p_vaddr..ph.p_vaddr + ph.p_memsz).contains(&offset))
+			.ok_or(LoadError::RelocationOutOfBounds(offset))?;
+
+		// Only the bytes of the relocation target that are backed by the file are read.
+		// The rest of the target is zero-initialized.
+		let file_len = ph.p_filesz.saturating_sub(offset - ph.p_vaddr);
+		let file_len = file_len.min(mem::size_of::<i64>() as u64) as usize;
+
+		let start = to_usize(offset - self.load_start_addr())?;
+		let loaded = memory
+			.get(start..)
+			.and_then(|memory| memory.get(..file_len))
+			.ok_or(LoadError::RelocationOutOfBounds(offset))?;
+		let mut bytes = [0; mem::size_of::<i64>()];
+		for (byte, loaded) in bytes.iter_mut().zip(loaded) {
+			// SAFETY: The file-backed part of each segment has been initialized by the transform.
+			*byte = unsafe { loaded.assume_init() };
+		}
+		Ok(i64::from_ne_bytes(bytes))
+	}
+
+	/// Allocates memory for the kernel and loads it there.
+	///
+	/// Returns the load information and the physical start address of the kernel.
+	pub fn load(&self) -> Result<(LoadInfo, usize), LoadError> {
+		let memory = self.allocate()?;
+		let start_address = memory.as_ptr() as usize;
+		let load_info = self.load_allocated(memory)?;
+		Ok((load_info, start_address))
+	}
+
+	/// Allocates memory for loading the kernel with [`Self::load_allocated`].
+	fn allocate(&self) -> Result<&'static mut [MaybeUninit<u8>], LoadError> {
+		let LoadPlan {
+			mem_size, align, ..
+		} = self.plan()?;
+		Ok(arch::allocate_kernel_memory(
+			mem_size,
+			align.try_into().unwrap(),
+		))
+	}
+
+	/// Loads the kernel into memory from [`Self::allocate`], which may be at the fixed address of an `ET_EXEC` kernel.
+	fn load_allocated(&self, memory: &mut [MaybeUninit<u8>]) -> Result<LoadInfo, LoadError> {
+		if self.header.e_type == header::ET_EXEC && memory.as_ptr() as u64 == self.load_start_addr()
+		{
+			self.load_fixed(memory)
+		} else {
+			self.load_kernel(memory)
+		}
+	}
+
+	/// Returns an overview of the kernel, which does not require loading it.
+	pub fn info(&self) -> KernelInfo<'a> {
+		let load_phs = || {
+			self.phs
+				.iter()
+				.filter(|ph| ph.p_type == program_header::PT_LOAD)
+		};
+
+		let SizeBreakdown {
+			file_size,
+			bss_size,
+			total: mem_size,
+		} = self.size_breakdown();
+
+		KernelInfo {
+			elf_type: self.header.e_type,
+			entry: self.header.e_entry,
+			mem_size,
+			file_size,
+			bss_size,
+			segment_count: load_phs().count(),
+			has_tls: self
+				.phs
+				.iter()
+				.any(|ph| ph.p_type == program_header::PT_TLS),
+			relocation_count: self.relas.len() + self.rels.len() + relr_offsets(self.relrs).count(),
+			build_id: self.build_id.map(BuildId),
+		}
+	}
+
+	/// Returns the amount by which the virtual addresses of the kernel are shifted when loading it to
+	/// `start_address`.
+	///
+	/// Position-independent kernels may be linked at a nonzero preferred base, which is replaced by `start_address`.
+	/// Other kernels keep their virtual addresses.
+	fn base(&self, start_address: u64) -> u64 {
+		if self.header.e_type == header::ET_DYN {
+			start_address.wrapping_sub(self.load_start_addr())
+		} else {
+			0
+		}
+	}
+
+	/// Computes the layout of the kernel without loading it.
+	///
+	/// The addresses in the returned plan are the kernel's link-time addresses.
+	/// When loading a position-independent kernel, they are shifted to the memory it is loaded to.
+	pub fn plan(&self) -> Result<LoadPlan, LoadError> {
+		self.plan_at(self.load_start_addr())
+	}
+
+	/// Computes the layout of the kernel when loaded to memory at `start_address`.
+	fn plan_at(&self, start_address: u64) -> Result<LoadPlan, LoadError> {
+		self.plan_with_base(self.base(start_address))
+	}
+
+	/// Computes the layout of the kernel when its virtual addresses are shifted by `base`.
+	fn plan_with_base(&self, base: u64) -> Result<LoadPlan, LoadError> {
+		let mut segments = [Segment::EMPTY; MAX_SEGMENTS];
+		let mut segment_count = 0;
+		for ph in self
+			.phs
+			.iter()
+			.filter(|ph| ph.p_type == program_header::PT_LOAD)
+		{
+			segments[segment_count] = Segment {
+				virtual_start: ph.p_vaddr.wrapping_add(base),
+				size: ph.p_memsz,
+				flags: ph.p_flags,
+			};
+			segment_count += 1;
+		}
 
 		let tls_info = self
 			.phs
 			.iter()
 			.find(|ph| ph.p_type == program_header::PT_TLS)
-			.map(|ph| TlsInfo::new(self.header, ph, memory.as_ptr() as u64));
+			.map(|ph| TlsInfo::new(ph, base));
+
+		let entry_point = self.header.e_entry.wrapping_add(base);
+		if align_down!(entry_point, arch::ENTRY_ALIGN) != entry_point {
+			return Err(LoadError::MisalignedEntryPoint(entry_point));
+		}
+		if !segments[..segment_count].iter().any(|segment| {
+			segment.executable()
+				&& (segment.virtual_start..segment.virtual_start + segment.size)
+					.contains(&entry_point)
+		}) {
+			return Err(LoadError::EntryPointNotExecutable(entry_point));
+		}
+
+		let elf_location =
+			(self.header.e_type == header::ET_EXEC).then_some(self.load_start_addr());
+
+		// The end of the RELRO region is rounded down, since the following data on the same page must stay writable.
+		let relro = self
+			.phs
+			.iter()
+			.find(|ph| ph.p_type == program_header::PT_GNU_RELRO)
+			.map(|ph| {
+				let start = ph.p_vaddr.wrapping_add(base);
+				let end = start + ph.p_memsz;
+				let page_size = BasePageSize::SIZE as u64;
+				align_down!(start, page_size)..align_down!(end, page_size)
+			});
+
+		Ok(LoadPlan {
+			mem_size: self.mem_size(),
+			align: self.required_align(),
+			info: LoadInfo {
+				elf_location,
+				entry_point,
+				tls_info,
+				relro,
+				segments,
+				segment_count,
+			},
+		})
+	}
+
+	/// Loads the kernel into the provided memory.
+	pub fn load_kernel(&self, memory: &mut [MaybeUninit<u8>]) -> Result<LoadInfo, LoadError> {
+		self.load_kernel_with(memory, |src, dst| arch::copy_memory(dst, src))
+	}
+
+	/// Loads an `ET_EXEC` kernel into the provided memory, which has to be located at the kernel's fixed address.
+	///
+	/// Unlike with [`Self::load_kernel`], the kernel does not have to be moved to [`LoadInfo::elf_location`]
+	/// afterwards.
+	pub fn load_fixed(&self, memory: &mut [MaybeUninit<u8>]) -> Result<LoadInfo, LoadError> {
+		if self.header.e_type != header::ET_EXEC {
+			return Err(LoadError::NoFixedAddress);
+		}
+		let fixed_address = self.load_start_addr();
+		if memory.as_ptr() as u64 != fixed_address {
+			return Err(LoadError::NotAtFixedAddress(fixed_address));
+		}
+
+		// The kernel is loaded to its link-time addresses, so nothing is shifted.
+		self.load_with_base(memory, 0, |_, src, dst| arch::copy_memory(dst, src))
+	}
+
+	/// Loads the kernel into the provided memory, transforming each segment while copying it.
+	///
+	/// For each loadable segment, `transform` is called with the segment's bytes from the ELF file and the memory
+	/// of the same length they are to be loaded to, e.g., for decrypting the segment.
+	/// `transform` must initialize all of the `p_filesz` bytes of the destination.
+	/// Relocations are applied to the transformed segments afterwards.
+	pub fn load_kernel_with(
+		&self,
+		memory: &mut [MaybeUninit<u8>],
+		mut transform: impl FnMut(&[u8], &mut [MaybeUninit<u8>]),
+	) -> Result<LoadInfo, LoadError> {
+		let base = self.base(memory.as_ptr() as u64);
+		self.load_with_base(memory, base, |_, src, dst| transform(src, dst))
+	}
+
+	/// Loads the kernel like [`Self::load_kernel`], but maps read-only segments from the ELF file instead of
+	/// copying them.
+	///
+	/// This saves the memory of large read-only segments, such as `.text` and `.rodata`.
+	/// A segment is mapped if it is neither writable nor relocated and has no zero-initialized part.
+	/// Only its whole pages are mapped, which requires the segment to be page-aligned both in the ELF file and in
+	/// memory. All other segments are copied.
+	///
+	/// The ELF file has to stay resident and unmodified for as long as the kernel runs.
+	/// Since the loaded kernel is not physically contiguous afterwards, the kernel has to access its image through
+	/// the loader's page tables.
+	#[allow(dead_code)]
+	pub fn load_mapped(&self, memory: &mut [MaybeUninit<u8>]) -> Result<LoadInfo, LoadError> {
+		let base = self.base(memory.as_ptr() as u64);
+		self.load_with_base(memory, base, |ph, src, dst| {
+			let shared = if self.is_shareable(ph) {
+				arch::share_memory(dst, src, ph.p_flags)
+			} else {
+				0
+			};
+			arch::copy_memory(&mut dst[shared..], &src[shared..]);
+		})
+	}
+
+	/// Returns whether the segment is never written to while loading or running the kernel.
+	fn is_shareable(&self, ph: &ProgramHeader) -> bool {
+		// Relocation targets lie within a single segment, so checking their start is sufficient.
+		let in_segment = |offset: u64| offset >= ph.p_vaddr && offset < ph.p_vaddr + ph.p_memsz;
+		ph.p_flags & program_header::PF_W == 0
+			&& ph.p_filesz == ph.p_memsz
+			&& !self.relas.iter().any(|rela| in_segment(rela.r_offset))
+			&& !self.rels.iter().any(|rel| in_segment(rel.r_offset))
+			&& !relr_offsets(self.relrs).any(in_segment)
+	}
+
+	/// Loads the kernel into the provided memory with its virtual addresses shifted by `base`.
+	///
+	/// `transform` is called like for [`Self::load_kernel_with`], but also receives the segment's program header.
+	fn load_with_base(
+		&self,
+		memory: &mut [MaybeUninit<u8>],
+		base: u64,
+		mut transform: impl FnMut(&ProgramHeader, &[u8], &mut [MaybeUninit<u8>]),
+	) -> Result<LoadInfo, LoadError> {
+		loaderlog!("Loading kernel to {memory:p}");
 
-		let entry_point = {
-			let mut entry_point = self.header.e_entry;
-			if self.header.e_type == header::ET_DYN {
-				entry_point += memory.as_ptr() as u64;
+		assert!(memory.len() >= self.mem_size());
+		assert_eq!(
+			memory.as_ptr() as u64 % self.required_align(),
+			0,
+			"kernel memory at {memory:p} is not aligned to {:#x}",
+			self.required_align()
+		);
+
+		let load_start_addr = self.load_start_addr();
+		let LoadPlan { info, .. } = self.plan_with_base(base)?;
+
+		// Indirect function resolvers run during loading, so they have to be at the address they are linked for.
+		// Resolvers of `ET_EXEC` kernels use absolute addresses and would compute addresses in a temporary copy.
+		if let Some(offset) = self.indirect_relocation() {
+			if self.header.e_type == header::ET_EXEC && memory.as_ptr() as u64 != load_start_addr {
+				return Err(LoadError::IndirectRelocationNotAtFixedAddress(offset));
 			}
-			entry_point
+		}
+
+		// Load program segments
+		// Contains TLS initialization image
+		for ph in self
+			.phs
+			.iter()
+			.filter(|ph| ph.p_type == program_header::PT_LOAD)
+		{
+			let ph_memory = {
+				let mem_start = to_usize(ph.p_vaddr - load_start_addr)?;
+				let mem_len = to_usize(ph.p_memsz)?;
+				&mut memory[mem_start..][..mem_len]
+			};
+			let file_len = to_usize(ph.p_filesz)?;
+			let ph_file = &self.elf[to_usize(ph.p_offset)?..][..file_len];
+			transform(ph, ph_file, &mut ph_memory[..file_len]);
+			arch::zero_memory(&mut ph_memory[file_len..]);
+		}
+
+		// Perform relocations
+		let total = self.relas.len() + self.rels.len();
+		// Relocations of `ET_EXEC` kernels already refer to their fixed addresses, so nothing is added to them.
+		let kernel_addr = base as i64;
+		let relocations = || {
+			self.relas
+				.iter()
+				.map(|rela| (rela.r_offset, rela.r_info, Some(rela.r_addend)))
+				.chain(self.rels.iter().map(|rel| (rel.r_offset, rel.r_info, None)))
 		};
+		relocations()
+			.filter(|&(_, info, _)| reloc::r_type(info) != arch::R_IRELATIVE)
+			.enumerate()
+			.try_for_each(|(i, (offset, info, addend))| {
+				if i > 0 && i % RELOCATION_PROGRESS_INTERVAL == 0 {
+					loaderlog!(debug, "Applied {i}/{total} relocations");
+				}
+
+				let base = match reloc::r_type(info) {
+					arch::R_RELATIVE => kernel_addr,
+					// x86-64 defines GLOB_DAT and JUMP_SLOT without addend, but linkers always emit an addend of zero.
+					arch::R_ABS64 | arch::R_GLOB_DAT | arch::R_JUMP_SLOT => {
+						self.dynamic_symbol_value(reloc::r_sym(info), kernel_addr, offset)?
+					}
+					_ => unreachable!(),
+				};
+				let addend = match addend {
+					Some(addend) => addend,
+					None => self.implicit_addend(memory, offset)?,
+				};
+				let relocated = base
+					.checked_add(addend)
+					.ok_or(LoadError::RelocationOverflow(offset))?;
+				write_relocated(memory, load_start_addr, offset, relocated.to_ne_bytes())
+			})?;
+
+		// Perform compact relative relocations
+		for offset in relr_offsets(self.relrs) {
+			let addend = self.implicit_addend(memory, offset)?;
+			let relocated = kernel_addr
+				.checked_add(addend)
+				.ok_or(LoadError::RelocationOverflow(offset))?;
+			write_relocated(memory, load_start_addr, offset, relocated.to_ne_bytes())?;
+		}
 
-		let elf_location = (self.header.e_type == header::ET_EXEC).then_some(load_start_addr);
+		// Perform indirect relocations
+		// Their resolvers may only run once everything else has been relocated.
+		relocations()
+			.filter(|&(_, info, _)| reloc::r_type(info) == arch::R_IRELATIVE)
+			.try_for_each(|(offset, _, addend)| {
+				let addend = match addend {
+					Some(addend) => addend,
+					None => self.implicit_addend(memory, offset)?,
+				};
+				let resolver = kernel_addr
+					.checked_add(addend)
+					.ok_or(LoadError::RelocationOverflow(offset))?;
+				let relocated = call_resolver(resolver as u64);
+				write_relocated(memory, load_start_addr, offset, relocated.to_ne_bytes())
+			})?;
 
-		LoadInfo {
-			elf_location,
-			entry_point,
-			tls_info,
+		if let Some(tls_info) = &info.tls_info {
+			let range = tls_info.start as *const ()..(tls_info.start + tls_info.memsz) as *const ();
+			let len = tls_info.memsz;
+			loaderlog!("TLS is at {range:?} ({len} B)",);
+		} else if self.has_symbol("__stack_chk_fail") || self.has_symbol("__stack_chk_guard") {
+			// Stack protectors read their canary from thread-local storage.
+			loaderlog!("WARNING: kernel uses a stack protector but has no TLS segment");
 		}
+
+		Ok(info)
 	}
 }
 
+/// Parses and loads the first valid of the given kernel images, e.g., for falling back to another kernel if the
+/// preferred one is damaged.
+///
+/// The memory allocated for an image that fails to load is freed before the next image is tried.
+/// Returns the load information and the index of the loaded image, or the error of the last image if none is valid.
+#[allow(dead_code)]
+pub fn load_first_valid(images: &[&[u8]]) -> Result<(LoadInfo, usize), LoadError> {
+	assert!(!images.is_empty(), "No kernel images to load");
+
+	let mut last_error = None;
+	for (index, image) in images.iter().enumerate() {
+		let mark = arch::mark_kernel_memory();
+		let mut memory = None;
+		let result = Object::parse(image)
+			.map_err(LoadError::from)
+			.and_then(|object| {
+				let memory = memory.insert(object.allocate()?);
+				object.load_allocated(memory)
+			});
+
+		match result {
+			Ok(load_info) => return Ok((load_info, index)),
+			Err(err) => {
+				loaderlog!("Kernel image {index} could not be loaded: {err}");
+				if let Some(memory) = memory {
+					arch::release_kernel_memory(mark, memory);
+				}
+				last_error = Some(err);
+			}
+		}
+	}
+
+	Err(last_error.unwrap())
+}
+
+/// Copies an initial ramdisk to the first page boundary of the provided memory.
+///
+/// `memory` must not overlap the loaded kernel and must have room for the ramdisk after aligning its start.
+///
+/// Returns the start address and the size of the placed ramdisk.
+pub fn place_initrd(memory: &mut [MaybeUninit<u8>], initrd: &[u8]) -> (u64, u64) {
+	let offset = {
+		let start = memory.as_ptr() as usize;
+		align_up!(start, BasePageSize::SIZE) - start
+	};
+	let dst = memory
+		.get_mut(offset..)
+		.and_then(|memory| memory.get_mut(..initrd.len()))
+		.expect("not enough memory for the initial ramdisk");
+	arch::copy_memory(dst, initrd);
+
+	let start = dst.as_ptr() as u64;
+	let len = initrd.len() as u64;
+	loaderlog!("Placed initial ramdisk at {start:#x} ({len} B)");
+	(start, len)
+}
+
+/// The sizes of the parts of a loaded kernel, see [`Object::size_breakdown`].
+#[derive(Debug, Clone, Copy)]
+pub struct SizeBreakdown {
+	/// Number of bytes copied from the ELF file (`p_filesz` of all loadable segments).
+	pub file_size: usize,
+
+	/// Number of zero-initialized bytes (`p_memsz - p_filesz` of all loadable segments).
+	pub bss_size: usize,
+
+	/// Size of the memory spanned by all loadable segments, including gaps and padding (see [`Object::mem_size`]).
+	pub total: usize,
+}
+
+/// An overview of a parsed kernel object.
+#[derive(Debug, Clone)]
+pub struct KernelInfo<'a> {
+	/// The ELF file type (`ET_EXEC` or `ET_DYN`).
+	pub elf_type: u16,
+
+	/// The entry point as specified in the ELF file, before relocation.
+	pub entry: u64,
+
+	/// Required memory size for loading (see [`Object::mem_size`]).
+	pub mem_size: usize,
+
+	/// Number of bytes copied from the ELF file when loading.
+	pub file_size: usize,
+
+	/// Number of bytes zero-initialized when loading.
+	pub bss_size: usize,
+
+	/// Number of loadable program segments.
+	pub segment_count: usize,
+
+	/// Whether the kernel has a thread-local storage segment.
+	pub has_tls: bool,
+
+	/// Number of relocations applied when loading.
+	pub relocation_count: usize,
+
+	/// The GNU build ID of the kernel, if present.
+	pub build_id: Option<BuildId<'a>>,
+}
+
+/// A GNU build ID, which is displayed as hexadecimal string, see [`Object::build_id`].
+#[derive(Debug, Clone, Copy)]
+pub struct BuildId<'a>(pub &'a [u8]);
+
+impl fmt::Display for BuildId<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for byte in self.0 {
+			write!(f, "{byte:02x}")?;
+		}
+		Ok(())
+	}
+}
+
+/// The hardening measures of a kernel, see [`Object::security_summary`].
+#[derive(Debug, Clone, Copy)]
+pub struct SecuritySummary {
+	/// Whether the kernel is position-independent (`ET_DYN`).
+	pub pie: bool,
+
+	/// Whether the kernel has a region that is read-only after relocation (`PT_GNU_RELRO`).
+	pub relro: bool,
+
+	/// Whether all relocations are processed before running the kernel (`DT_BIND_NOW`, `DF_BIND_NOW`, or `DF_1_NOW`).
+	pub bind_now: bool,
+
+	/// Whether the kernel's stack is not executable (see [`Object::stack_executable`]).
+	pub nx_stack: bool,
+}
+
+/// The layout of a kernel, see [`Object::plan`].
+pub struct LoadPlan {
+	/// Required memory size for loading (see [`Object::mem_size`]).
+	pub mem_size: usize,
+
+	/// Required memory alignment for loading (see [`Object::required_align`]).
+	pub align: u64,
+
+	/// The load information the kernel would be loaded with.
+	pub info: LoadInfo,
+}
+
 pub struct LoadInfo {
 	pub elf_location: Option<u64>,
 	pub entry_point: u64,
 	pub tls_info: Option<TlsInfo>,
+
+	/// Page-aligned virtual address range that may be made read-only after loading (`PT_GNU_RELRO`).
+	pub relro: Option<Range<u64>>,
+
+	/// The loaded program segments, see [`Self::segments`].
+	segments: [Segment; MAX_SEGMENTS],
+	segment_count: usize,
+}
+
+impl LoadInfo {
+	/// Returns the loaded program segments.
+	///
+	/// The kernel expects its segments to be mapped with the permissions given by their flags.
+	pub fn segments(&self) -> &[Segment] {
+		&self.segments[..self.segment_count]
+	}
+}
+
+/// A loaded program segment.
+#[derive(Clone, Copy, Debug)]
+pub struct Segment {
+	/// Virtual address of the start of this segment.
+	pub virtual_start: u64,
+
+	/// Size of this segment in memory.
+	pub size: u64,
+
+	/// Permissions of this segment (`PF_R`, `PF_W`, and `PF_X`).
+	pub flags: u32,
+}
+
+impl Segment {
+	const EMPTY: Self = Self {
+		virtual_start: 0,
+		size: 0,
+		flags: 0,
+	};
+
+	/// Returns whether this segment may be read from (`PF_R`).
+	pub fn readable(&self) -> bool {
+		self.flags & program_header::PF_R != 0
+	}
+
+	/// Returns whether this segment may be written to (`PF_W`).
+	pub fn writable(&self) -> bool {
+		self.flags & program_header::PF_W != 0
+	}
+
+	/// Returns whether this segment may be executed (`PF_X`).
+	pub fn executable(&self) -> bool {
+		self.flags & program_header::PF_X != 0
+	}
 }
 
 pub struct TlsInfo {
@@ -216,21 +1489,32 @@ pub struct TlsInfo {
 }
 
 impl TlsInfo {
-	fn new(header: &Header, ph: &ProgramHeader, start_addr: u64) -> Self {
-		let mut tls_start = ph.p_vaddr;
-		if header.e_type == header::ET_DYN {
-			tls_start += start_addr;
-		}
-		let tls_info = TlsInfo {
+	/// Minimum alignment of the TLS block handed to the kernel.
+	///
+	/// The kernel places pointer-sized data next to the TLS block.
+	const MIN_ALIGN: u64 = mem::align_of::<u64>() as u64;
+
+	/// Creates the TLS information of a kernel whose virtual addresses have been shifted by `base` when loading.
+	fn new(ph: &ProgramHeader, base: u64) -> Self {
+		let tls_start = ph.p_vaddr.wrapping_add(base);
+		// An alignment of 0 means no alignment constraints, just as 1 does.
+		let align = ph.p_align.max(1);
+		assert!(
+			align.is_power_of_two(),
+			"TLS alignment {align:#x} is not a power of two"
+		);
+		assert!(
+			ph.p_filesz <= ph.p_memsz,
+			"TLS initialization image ({:#x} B) is larger than the TLS segment ({:#x} B)",
+			ph.p_filesz,
+			ph.p_memsz
+		);
+		TlsInfo {
 			start: tls_start,
 			filesz: ph.p_filesz,
 			memsz: ph.p_memsz,
-			align: ph.p_align,
-		};
-		let range = tls_info.start as *const ()..(tls_info.start + tls_info.memsz) as *const ();
-		let len = tls_info.memsz;
-		loaderlog!("TLS is at {range:?} ({len} B)",);
-		tls_info
+			align: align.max(Self::MIN_ALIGN),
+		}
 	}
 
 	pub fn insert_into(&self, boot_info: &mut BootInfo) {
@@ -239,4 +1523,98 @@ impl TlsInfo {
 		boot_info.tls_memsz = self.memsz;
 		boot_info.tls_align = self.align;
 	}
+
+	/// Zeroes the TLS fields of `boot_info` for a kernel without TLS segment.
+	pub fn clear(boot_info: &mut BootInfo) {
+		boot_info.tls_start = 0;
+		boot_info.tls_filesz = 0;
+		boot_info.tls_memsz = 0;
+		boot_info.tls_align = 0;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Size of the ELF files assembled by the tests.
+	const ELF_SIZE: usize = 0x100;
+
+	/// An ELF file that is aligned like the headers it contains.
+	#[repr(align(8))]
+	struct Elf([u8; ELF_SIZE]);
+
+	/// Assembles a position-independent kernel with a single executable segment at `vaddr`, aligned to `align`.
+	fn pie_kernel(vaddr: u64, align: u64) -> Elf {
+		let mut elf = Elf([0; ELF_SIZE]);
+		let mut put = |offset: usize, bytes: &[u8]| {
+			elf.0[offset..][..bytes.len()].copy_from_slice(bytes);
+		};
+
+		// ELF header
+		let phoff = header::SIZEOF_EHDR;
+		put(0, header::ELFMAG);
+		put(header::EI_CLASS, &[header::ELFCLASS64]);
+		put(header::EI_DATA, &[header::ELFDATA2LSB]);
+		put(16, &header::ET_DYN.to_le_bytes());
+		put(18, &arch::ELF_ARCH.to_le_bytes());
+		put(24, &(vaddr + 0x40).to_le_bytes());
+		put(32, &(phoff as u64).to_le_bytes());
+		put(56, &1u16.to_le_bytes());
+
+		// Program header of the executable segment
+		put(phoff, &program_header::PT_LOAD.to_le_bytes());
+		put(
+			phoff + 4,
+			&(program_header::PF_R | program_header::PF_X).to_le_bytes(),
+		);
+		put(phoff + 16, &vaddr.to_le_bytes());
+		put(phoff + 32, &(ELF_SIZE as u64).to_le_bytes());
+		put(phoff + 40, &0x1000u64.to_le_bytes());
+		put(phoff + 48, &align.to_le_bytes());
+
+		elf
+	}
+
+	#[test]
+	fn base_honors_segment_alignment() {
+		const ALIGN: u64 = 0x20_0000;
+
+		let elf = pie_kernel(0x20_1000, ALIGN);
+		let object = Object::parse(&elf.0).unwrap();
+		assert_eq!(object.required_align(), ALIGN);
+
+		let plan = object.plan().unwrap();
+		assert_eq!(plan.align, ALIGN);
+
+		let start_address = 0x4000_0000 + 7 * ALIGN;
+		let base = object.base(start_address);
+		assert_eq!(base % ALIGN, 0);
+
+		let plan = object.plan_at(start_address).unwrap();
+		let segment = &plan.info.segments[0];
+		assert_eq!(segment.virtual_start % ALIGN, 0x1000);
+		assert_eq!(segment.virtual_start, start_address + 0x1000);
+		assert_eq!(plan.info.entry_point, segment.virtual_start + 0x40);
+	}
+
+	#[test]
+	fn only_read_only_segments_are_shared() {
+		let phoff = header::SIZEOF_EHDR;
+		let mut elf = pie_kernel(0x20_0000, 0x1000);
+		let is_shareable = |elf: &Elf| {
+			let object = Object::parse(&elf.0).unwrap();
+			object.is_shareable(&object.phs[0])
+		};
+
+		// The segment ends with `.bss`.
+		assert!(!is_shareable(&elf));
+
+		elf.0[phoff + 40..][..8].copy_from_slice(&(ELF_SIZE as u64).to_le_bytes());
+		assert!(is_shareable(&elf));
+
+		elf.0[phoff + 4..][..4]
+			.copy_from_slice(&(program_header::PF_R | program_header::PF_W).to_le_bytes());
+		assert!(!is_shareable(&elf));
+	}
 }
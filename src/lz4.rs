@@ -0,0 +1,243 @@
+//! Decompression of LZ4-compressed kernel images.
+//!
+//! Only the LZ4 frame format is supported, as created by `lz4 --content-size`.
+//! Checksums are skipped.
+
+use core::mem::MaybeUninit;
+use core::slice;
+
+/// Magic number at the start of an LZ4 frame.
+const MAGIC: u32 = 0x184D_2204;
+
+/// Version of the frame format in the upper bits of the FLG byte.
+const VERSION: u8 = 0b01;
+
+/// FLG bit: each block is followed by a checksum.
+const FLG_BLOCK_CHECKSUM: u8 = 1 << 4;
+
+/// FLG bit: the frame header contains the uncompressed size of the content.
+const FLG_CONTENT_SIZE: u8 = 1 << 3;
+
+/// FLG bit: the frame ends with a checksum of the content.
+const FLG_CONTENT_CHECKSUM: u8 = 1 << 2;
+
+/// FLG bit: the frame header contains a dictionary ID.
+const FLG_DICT_ID: u8 = 1 << 0;
+
+/// Bit of a block size marking the block as stored uncompressed.
+const BLOCK_UNCOMPRESSED: u32 = 1 << 31;
+
+/// Minimum length of a match, which is not encoded in the sequence token.
+const MIN_MATCH: usize = 4;
+
+/// An error that occurred while decompressing an LZ4 frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DecompressError {
+	/// The frame is cut off.
+	Truncated,
+
+	/// The frame uses an unsupported version or feature, or does not contain its content size.
+	Unsupported,
+
+	/// A block refers to data before the start of the content.
+	Malformed,
+
+	/// The content does not fit into the provided memory.
+	OutputTooSmall,
+}
+
+/// A reader for the bytes of an LZ4 frame.
+struct Reader<'a> {
+	input: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+	fn bytes(&mut self, len: usize) -> Result<&'a [u8], DecompressError> {
+		if len > self.input.len() {
+			return Err(DecompressError::Truncated);
+		}
+		let (bytes, rest) = self.input.split_at(len);
+		self.input = rest;
+		Ok(bytes)
+	}
+
+	fn u8(&mut self) -> Result<u8, DecompressError> {
+		Ok(self.bytes(1)?[0])
+	}
+
+	fn u16(&mut self) -> Result<u16, DecompressError> {
+		Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+	}
+
+	fn u32(&mut self) -> Result<u32, DecompressError> {
+		Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+	}
+
+	fn u64(&mut self) -> Result<u64, DecompressError> {
+		Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+	}
+
+	/// Reads the continuation of a literal or match length, which is a sum of bytes ending with a byte below 255.
+	fn length(&mut self) -> Result<usize, DecompressError> {
+		let mut length = 0;
+		loop {
+			let byte = self.u8()?;
+			length += usize::from(byte);
+			if byte != u8::MAX {
+				return Ok(length);
+			}
+		}
+	}
+}
+
+/// The parsed header of an LZ4 frame.
+struct FrameHeader {
+	flags: u8,
+	content_size: usize,
+}
+
+impl FrameHeader {
+	fn read(reader: &mut Reader<'_>) -> Result<Self, DecompressError> {
+		if reader.u32()? != MAGIC {
+			return Err(DecompressError::Unsupported);
+		}
+
+		let flags = reader.u8()?;
+		let _block_descriptor = reader.u8()?;
+		if flags >> 6 != VERSION || flags & FLG_CONTENT_SIZE == 0 {
+			return Err(DecompressError::Unsupported);
+		}
+
+		let content_size = reader
+			.u64()?
+			.try_into()
+			.map_err(|_| DecompressError::Unsupported)?;
+		if flags & FLG_DICT_ID != 0 {
+			reader.u32()?;
+		}
+		let _header_checksum = reader.u8()?;
+
+		Ok(Self {
+			flags,
+			content_size,
+		})
+	}
+}
+
+/// Returns whether the given data starts like an LZ4 frame.
+pub fn is_compressed(input: &[u8]) -> bool {
+	input.get(..4) == Some(&MAGIC.to_le_bytes())
+}
+
+/// Returns the size of the decompressed content of the given LZ4 frame as stated in its header.
+pub fn decompressed_size(input: &[u8]) -> Result<usize, DecompressError> {
+	FrameHeader::read(&mut Reader { input }).map(|header| header.content_size)
+}
+
+/// Decompresses the given LZ4 frame into `output`.
+///
+/// Returns the decompressed content at the start of `output`, which must be at least [`decompressed_size`] long.
+pub fn decompress<'a>(
+	input: &[u8],
+	output: &'a mut [MaybeUninit<u8>],
+) -> Result<&'a mut [u8], DecompressError> {
+	let mut reader = Reader { input };
+	let header = FrameHeader::read(&mut reader)?;
+	let output = output
+		.get_mut(..header.content_size)
+		.ok_or(DecompressError::OutputTooSmall)?;
+
+	// Blocks may refer to the content of previous blocks, which stays in place in `output`.
+	let mut len = 0;
+	loop {
+		let block_size = reader.u32()?;
+		if block_size == 0 {
+			break;
+		}
+
+		let block = reader.bytes((block_size & !BLOCK_UNCOMPRESSED) as usize)?;
+		len = if block_size & BLOCK_UNCOMPRESSED != 0 {
+			let end = len + block.len();
+			MaybeUninit::write_slice(
+				output
+					.get_mut(len..end)
+					.ok_or(DecompressError::OutputTooSmall)?,
+				block,
+			);
+			end
+		} else {
+			decompress_block(block, output, len)?
+		};
+
+		if header.flags & FLG_BLOCK_CHECKSUM != 0 {
+			reader.u32()?;
+		}
+	}
+
+	if header.flags & FLG_CONTENT_CHECKSUM != 0 {
+		reader.u32()?;
+	}
+
+	if len != header.content_size {
+		return Err(DecompressError::Truncated);
+	}
+
+	// SAFETY: All `len` bytes have been written above.
+	Ok(unsafe { slice::from_raw_parts_mut(output.as_mut_ptr().cast::<u8>(), len) })
+}
+
+/// Decompresses a single block, appending it to the `len` bytes of `output` that have already been written.
+///
+/// Returns the new length of the written output.
+fn decompress_block(
+	block: &[u8],
+	output: &mut [MaybeUninit<u8>],
+	mut len: usize,
+) -> Result<usize, DecompressError> {
+	let mut reader = Reader { input: block };
+
+	// Each sequence consists of literals, which are copied as they are, followed by a match,
+	// which repeats earlier output. The last sequence of a block ends after its literals.
+	loop {
+		let token = reader.u8()?;
+
+		let mut literal_len = usize::from(token >> 4);
+		if literal_len == 0xF {
+			literal_len += reader.length()?;
+		}
+		let literals = reader.bytes(literal_len)?;
+		let end = len + literal_len;
+		MaybeUninit::write_slice(
+			output
+				.get_mut(len..end)
+				.ok_or(DecompressError::OutputTooSmall)?,
+			literals,
+		);
+		len = end;
+
+		if reader.input.is_empty() {
+			return Ok(len);
+		}
+
+		let offset = usize::from(reader.u16()?);
+		if offset == 0 || offset > len {
+			return Err(DecompressError::Malformed);
+		}
+
+		let mut match_len = usize::from(token & 0xF);
+		if match_len == 0xF {
+			match_len += reader.length()?;
+		}
+		match_len += MIN_MATCH;
+
+		if len + match_len > output.len() {
+			return Err(DecompressError::OutputTooSmall);
+		}
+
+		// The match may overlap the bytes it produces, so it has to be copied byte by byte.
+		for i in len..len + match_len {
+			output[i] = output[i - offset];
+		}
+		len += match_len;
+	}
+}
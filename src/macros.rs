@@ -33,11 +33,28 @@ macro_rules! println {
 }
 
 /// Print formatted loader log messages to our console, followed by a newline.
+///
+/// Messages may start with a log level (`info`, `debug`, or `trace`) and default to `info`.
+/// `debug` messages are only printed with the `log-debug` feature and `trace` messages only with the `log-trace`
+/// feature. Otherwise, they are compiled out.
 #[macro_export]
 macro_rules! loaderlog {
-    ($($arg:tt)*) => {{
+    (info, $($arg:tt)*) => {{
         print!("[LOADER] {}\n", ::core::format_args!($($arg)*))
     }};
+    (debug, $($arg:tt)*) => {{
+        if cfg!(feature = "log-debug") {
+            print!("[LOADER][DEBUG] {}\n", ::core::format_args!($($arg)*))
+        }
+    }};
+    (trace, $($arg:tt)*) => {{
+        if cfg!(feature = "log-trace") {
+            print!("[LOADER][TRACE] {}\n", ::core::format_args!($($arg)*))
+        }
+    }};
+    ($($arg:tt)*) => {{
+        $crate::loaderlog!(info, $($arg)*)
+    }};
 }
 
 /// Prints and returns the value of a given expression for quick and dirty
@@ -1,5 +1,5 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(asm_const)]
 #![feature(maybe_uninit_write_slice)]
 #![feature(specialization)]
@@ -12,7 +12,9 @@ mod macros;
 
 mod arch;
 mod console;
+mod digest;
 mod kernel;
+mod lz4;
 
 use core::{
 	fmt::{self, Write},
@@ -24,8 +26,10 @@ use core::{
 // Workaround for https://github.com/hermitcore/rusty-loader/issues/117
 use rusty_loader as _;
 
+use arch::paging::{BasePageSize, PageSize};
 use arch::BOOT_INFO;
-use kernel::{LoadInfo, Object};
+use digest::Digest;
+use kernel::{BuildId, LoadInfo, Object, TlsInfo};
 
 extern "C" {
 	static kernel_end: u8;
@@ -45,29 +49,76 @@ unsafe extern "C" fn loader_main() -> ! {
 		&kernel_end as *const u8 as usize
 	);
 
-	let kernel = Object::parse(arch::find_kernel());
+	let elf = arch::find_kernel();
+	let kernel = match Digest::expected() {
+		Some(expected) => Object::parse_verified(elf, &expected, arch::allocate_scratch_memory),
+		None => Object::parse_compressed(elf, arch::allocate_scratch_memory),
+	}
+	.unwrap_or_else(|err| panic!("kernel could not be parsed: {err}"));
+	loaderlog!(debug, "{:?}", kernel.info());
+	loaderlog!(debug, "{:?}", kernel.security_summary());
+	if let Some(build_id) = kernel.build_id() {
+		loaderlog!("Kernel build ID: {}", BuildId(build_id));
+	}
 
-	let memory = {
-		let mem_size = kernel.mem_size();
-		let kernel_addr = arch::get_memory(mem_size as u64);
-		slice::from_raw_parts_mut(kernel_addr as *mut MaybeUninit<u8>, mem_size)
-	};
+	let (load_info, kernel_addr) = kernel
+		.load()
+		.unwrap_or_else(|err| panic!("kernel could not be loaded: {err}"));
+	for segment in load_info.segments() {
+		loaderlog!(
+			debug,
+			"Segment at {:#x} ({:#x} B, {}{}{})",
+			segment.virtual_start,
+			segment.size,
+			if segment.readable() { 'r' } else { '-' },
+			if segment.writable() { 'w' } else { '-' },
+			if segment.executable() { 'x' } else { '-' }
+		);
+	}
+
+	if let Some(start) = kernel.symbol("_start", kernel_addr as u64) {
+		loaderlog!(debug, "Kernel symbol _start at {start:#x}");
+	}
+
+	if let Some(relro) = &load_info.relro {
+		loaderlog!(debug, "Relocation read-only region at {relro:#x?}");
+
+		// All relocations have been applied, so the region can be made read-only.
+		// Kernels that are moved to their fixed address when booting are not mapped there yet.
+		if load_info
+			.elf_location
+			.map_or(true, |address| address == kernel_addr as u64)
+		{
+			arch::write_protect(relro.clone());
+		}
+	}
 
 	let LoadInfo {
 		elf_location,
 		entry_point,
 		tls_info,
-	} = kernel.load_kernel(memory);
+		..
+	} = load_info;
+
+	match tls_info {
+		Some(tls_info) => tls_info.insert_into(&mut BOOT_INFO),
+		None => TlsInfo::clear(&mut BOOT_INFO),
+	}
 
-	if let Some(tls_info) = tls_info {
-		tls_info.insert_into(&mut BOOT_INFO);
+	// The memory for the initial ramdisk is allocated after the kernel's memory, so they do not overlap.
+	if let Some(initrd) = arch::find_initrd() {
+		let memory = arch::allocate_kernel_memory(initrd.len(), BasePageSize::SIZE);
+		let (ramdisk_image, ramdisk_len) = kernel::place_initrd(memory, initrd);
+		BOOT_INFO.ramdisk_image = ramdisk_image;
+		BOOT_INFO.ramdisk_len = ramdisk_len;
 	}
 
 	arch::boot_kernel(
 		elf_location,
-		memory.as_ptr() as u64,
-		memory.len() as u64,
+		kernel_addr as u64,
+		kernel.mem_size() as u64,
 		entry_point,
+		kernel.stack_executable(),
 	)
 }
 
@@ -84,6 +135,7 @@ unsafe fn init_bss() {
 	slice.fill(MaybeUninit::new(0));
 }
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
 	// We can't use `println!` or related macros, because `_print` unwraps a result and might panic again